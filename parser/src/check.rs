@@ -0,0 +1,819 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    errors::{Error, ErrorType},
+    expr::{Comprehension, Expr},
+    pattern::{MatchArm, Pattern},
+    statement::Statement,
+    tokens::Token,
+    Parser,
+};
+
+/// The type of a value, as far as the type checker can tell.
+/// `Unknown` is used for everything it can't infer, and is compatible with any other type.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Type {
+    Number,
+    String,
+    Boolean,
+    None,
+    List,
+    Tuple,
+    Map,
+    Range,
+    Function {
+        params: Vec<Type>,
+        returns: Box<Type>,
+    },
+    Unknown,
+}
+
+impl Type {
+    /// The type written in an annotation, like `Number` in `x: Number = 1`
+    fn from_annotation(annotation: &str) -> Option<Type> {
+        Some(match annotation {
+            "Number" => Type::Number,
+            "String" => Type::String,
+            "Boolean" => Type::Boolean,
+            "None" => Type::None,
+            "List" => Type::List,
+            "Tuple" => Type::Tuple,
+            "Map" => Type::Map,
+            "Range" => Type::Range,
+            "Function" => Type::Function {
+                params: vec![],
+                returns: Box::new(Type::Unknown),
+            },
+            _ => return None,
+        })
+    }
+
+    /// Check if a value of type `other` can be used where this type is expected
+    pub fn accepts(&self, other: &Type) -> bool {
+        match (self, other) {
+            (Type::Unknown, _) | (_, Type::Unknown) => true,
+            // An annotation only says it's a function, without its signature
+            (Type::Function { params, .. }, Type::Function { .. }) if params.is_empty() => true,
+            (
+                Type::Function { params, returns },
+                Type::Function {
+                    params: other_params,
+                    returns: other_returns,
+                },
+            ) => {
+                params.len() == other_params.len()
+                    && params.iter().zip(other_params).all(|(a, b)| a.accepts(b))
+                    && returns.accepts(other_returns)
+            }
+            _ => self == other,
+        }
+    }
+
+    /// The type of a value that is either of these two types
+    fn union(self, other: Type) -> Type {
+        if self == other {
+            self
+        } else {
+            Type::Unknown
+        }
+    }
+}
+
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Type::Function { params, returns } => {
+                let params: Vec<String> = params.iter().map(Type::to_string).collect();
+                write!(f, "fn({}) -> {}", params.join(", "), returns)
+            }
+            Type::Unknown => write!(f, "?"),
+            other => write!(f, "{:?}", other),
+        }
+    }
+}
+
+/// A variable known to the type checker
+struct Variable {
+    ty: Type,
+    /// Set when the type comes from an annotation, so assigning another type to the variable is an error
+    annotated: bool,
+}
+
+/// The function whose body is being checked
+struct FunctionContext {
+    /// The annotated return type, if any
+    declared: Option<Type>,
+    /// The types of the values returned so far
+    returned: Vec<Type>,
+}
+
+/// A pass over a parsed program that reports the operations on values of the wrong type.
+/// The types come from the annotations, and from a local inference over the builtin classes:
+/// literals, arithmetic, comparisons and calls to functions declared in the program.
+/// The program must be parsed with `Parser::positions` set for the errors to point at the right statement.
+pub struct TypeChecker {
+    input: String,
+    scopes: Vec<HashMap<String, Variable>>,
+    functions: Vec<FunctionContext>,
+    /// The names of the enums, classes and interfaces declared so far, which can be used in annotations
+    types: HashSet<String>,
+    /// The position where the statement or call being checked starts
+    pos: usize,
+    errors: Vec<Error>,
+}
+
+/// Parse a program and check its types, returning the type errors found in it
+pub fn check(input: &str) -> Result<Vec<Error>, Error> {
+    let mut parser = Parser::new(input);
+    parser.positions = true;
+    let (program, _) = parser.parse_program()?;
+    Ok(TypeChecker::new(input).check(&program))
+}
+
+impl TypeChecker {
+    pub fn new(input: &str) -> Self {
+        TypeChecker {
+            input: input.to_string(),
+            scopes: vec![HashMap::new()],
+            functions: vec![],
+            types: HashSet::new(),
+            pos: 0,
+            errors: vec![],
+        }
+    }
+
+    /// Check a program, returning the type errors found in it
+    pub fn check(mut self, program: &Statement) -> Vec<Error> {
+        self.statement(program);
+        self.errors
+    }
+
+    fn error(&mut self, error_type: ErrorType) {
+        // The parser errors are at the position after the character they point at
+        let first = self.input[self.pos..].chars().next();
+        let pos = self.pos + first.map_or(0, char::len_utf8);
+        self.errors
+            .push(Error::new(error_type, pos, self.input.clone()));
+    }
+
+    /// Report an error if a value of type `found` is used where `expected` is
+    fn expect(&mut self, expected: &Type, found: &Type) {
+        if !expected.accepts(found) {
+            self.error(ErrorType::TypeMismatch {
+                expected: expected.to_string(),
+                found: found.to_string(),
+            });
+        }
+    }
+
+    /// The type named by an annotation. The types declared by the program are checked at runtime only,
+    /// so their values have an unknown type.
+    fn annotation(&mut self, annotation: &str) -> Type {
+        match Type::from_annotation(annotation) {
+            Some(ty) => ty,
+            None if self.types.contains(annotation) => Type::Unknown,
+            None => {
+                self.error(ErrorType::UnknownType(annotation.to_string()));
+                Type::Unknown
+            }
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<&Variable> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    fn declare(&mut self, name: &str, ty: Type, annotated: bool) {
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .insert(name.to_string(), Variable { ty, annotated });
+    }
+
+    /// Give a value to a variable. A variable assigned values of different types is left with an unknown type,
+    /// unless it is annotated, in which case the other types are errors.
+    fn assign(&mut self, name: &str, ty: Type) {
+        let previous = self
+            .scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(name));
+        match previous {
+            Some(variable) if variable.annotated => {
+                let expected = variable.ty.clone();
+                self.expect(&expected, &ty);
+            }
+            Some(variable) => variable.ty = variable.ty.clone().union(ty),
+            None => self.declare(name, ty, false),
+        }
+    }
+
+    /// Bind the names of a pattern matching a value of type `ty`
+    fn bind(&mut self, pattern: &Pattern, ty: Type) {
+        match pattern {
+            Pattern::Typed {
+                pattern,
+                annotation,
+            } => {
+                let annotated = self.annotation(annotation);
+                self.expect(&annotated, &ty);
+                match &**pattern {
+                    Pattern::Binding(name) => self.declare(name, annotated, true),
+                    pattern => self.bind(pattern, annotated),
+                }
+            }
+            Pattern::Binding(name) => self.assign(name, ty),
+            Pattern::Literal(expr) => {
+                self.expr(expr);
+            }
+            Pattern::Tuple(patterns)
+            | Pattern::List(patterns)
+            | Pattern::Alternatives(patterns)
+            | Pattern::Class {
+                fields: patterns, ..
+            } => {
+                for pattern in patterns {
+                    self.bind(pattern, Type::Unknown);
+                }
+            }
+            Pattern::Rest(Some(name)) => self.assign(name, Type::Unknown),
+            Pattern::Wildcard | Pattern::Rest(None) => {}
+        }
+    }
+
+    /// Check statements in a new scope, returning the type of the last one if it is an expression
+    fn block(&mut self, statements: &[Statement]) -> Type {
+        self.scopes.push(HashMap::new());
+        let mut ty = Type::None;
+        for statement in statements {
+            if let Statement::Located { pos, .. } = statement {
+                self.pos = *pos;
+            }
+            ty = match statement.unlocated() {
+                Statement::Expr(expr) => self.expr(expr),
+                statement => {
+                    self.statement(statement);
+                    Type::None
+                }
+            };
+        }
+        self.scopes.pop();
+        ty
+    }
+
+    fn condition(&mut self, condition: &Expr) {
+        let ty = self.expr(condition);
+        self.expect(&Type::Boolean, &ty);
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Located { pos, statement } => {
+                self.pos = *pos;
+                self.statement(statement);
+            }
+            Statement::Block(statements) => {
+                for statement in statements {
+                    self.statement(statement);
+                }
+            }
+            Statement::Expr(expr) | Statement::Defer(expr) => {
+                self.expr(expr);
+            }
+            Statement::Return(expr) => {
+                let ty = self.expr(expr);
+                if let Some(function) = self.functions.last_mut() {
+                    function.returned.push(ty.clone());
+                    if let Some(declared) = function.declared.clone() {
+                        self.expect(&declared, &ty);
+                    }
+                }
+            }
+            Statement::Fn {
+                name,
+                params,
+                return_type,
+                body,
+            } => self.function(name, params, return_type, body),
+            Statement::Assign(name, value) => {
+                let ty = self.expr(value);
+                self.assign(name, ty);
+            }
+            Statement::Destructure(pattern, value) => {
+                let ty = self.expr(value);
+                self.bind(pattern, ty);
+            }
+            Statement::Let { pattern, value, .. } => {
+                let ty = self.expr(value);
+                match pattern {
+                    Pattern::Binding(name) => self.declare(name, ty, false),
+                    pattern => {
+                        for name in pattern.bindings() {
+                            self.declare(&name, Type::Unknown, false);
+                        }
+                        self.bind(pattern, ty);
+                    }
+                }
+            }
+            Statement::If {
+                condition,
+                body,
+                else_body,
+            } => {
+                self.condition(condition);
+                self.block(body);
+                self.block(else_body);
+            }
+            Statement::Match { subject, arms } => {
+                self.match_arms(subject, arms);
+            }
+            Statement::For {
+                pattern,
+                iterable,
+                body,
+            } => {
+                self.scopes.push(HashMap::new());
+                self.for_head(pattern, iterable);
+                self.block(body);
+                self.scopes.pop();
+            }
+            Statement::Assert {
+                condition, message, ..
+            } => {
+                self.condition(condition);
+                if let Some(message) = message {
+                    self.expr(message);
+                }
+            }
+            Statement::With {
+                resource,
+                binding,
+                body,
+            } => {
+                self.expr(resource);
+                self.scopes.push(HashMap::new());
+                if let Some(binding) = binding {
+                    self.bind(binding, Type::Unknown);
+                }
+                self.block(body);
+                self.scopes.pop();
+            }
+            Statement::Enum { name, .. } => {
+                self.types.insert(name.clone());
+                self.declare(name, Type::Unknown, false);
+            }
+            Statement::SetField { object, value, .. } => {
+                self.expr(object);
+                self.expr(value);
+            }
+            Statement::Class {
+                name,
+                fields,
+                methods,
+                statics,
+                ..
+            } => {
+                self.types.insert(name.clone());
+                // The class is called with its fields to create an instance
+                let constructor = Type::Function {
+                    params: vec![Type::Unknown; fields.len()],
+                    returns: Box::new(Type::Unknown),
+                };
+                self.assign(name, constructor);
+                for (_, value) in statics {
+                    self.expr(value);
+                }
+                for method in methods {
+                    let (param_types, declared) =
+                        self.signature(&method.params, &method.return_type);
+                    self.function_body(&method.params, &param_types, &declared, &method.body);
+                }
+            }
+            Statement::Interface { name, methods } => {
+                self.types.insert(name.clone());
+                for method in methods {
+                    self.signature(&method.params, &method.return_type);
+                }
+            }
+            Statement::Macro { .. } => {}
+        }
+    }
+
+    /// Check a function declaration. Without annotation, its return type is inferred when its body
+    /// ends with a `return` and all the returned values have the same type.
+    fn function(
+        &mut self,
+        name: &str,
+        params: &[Pattern],
+        return_type: &Option<String>,
+        body: &[Statement],
+    ) {
+        let (param_types, declared) = self.signature(params, return_type);
+        let signature = |returns: Type| Type::Function {
+            params: param_types.clone(),
+            returns: Box::new(returns),
+        };
+        // Declared before checking the body, so recursive calls are checked too
+        self.assign(name, signature(declared.clone().unwrap_or(Type::Unknown)));
+
+        let returns = self.function_body(params, &param_types, &declared, body);
+        if declared.is_none() {
+            if let Some(variable) = self
+                .scopes
+                .iter_mut()
+                .rev()
+                .find_map(|scope| scope.get_mut(name))
+            {
+                if !variable.annotated {
+                    variable.ty = signature(returns);
+                }
+            }
+        }
+    }
+
+    /// The types of the parameters and the return value of a function, from their annotations
+    fn signature(
+        &mut self,
+        params: &[Pattern],
+        return_type: &Option<String>,
+    ) -> (Vec<Type>, Option<Type>) {
+        let param_types = params
+            .iter()
+            .map(|param| match param {
+                Pattern::Typed { annotation, .. } => self.annotation(annotation),
+                _ => Type::Unknown,
+            })
+            .collect();
+        let declared = return_type
+            .as_ref()
+            .map(|annotation| self.annotation(annotation));
+        (param_types, declared)
+    }
+
+    /// Check the body of a function, returning the type of the values it returns
+    fn function_body(
+        &mut self,
+        params: &[Pattern],
+        param_types: &[Type],
+        declared: &Option<Type>,
+        body: &[Statement],
+    ) -> Type {
+        let pos = self.pos;
+        self.scopes.push(HashMap::new());
+        for (param, ty) in params.iter().zip(param_types) {
+            match param {
+                Pattern::Typed { pattern, .. } => match &**pattern {
+                    Pattern::Binding(name) => self.declare(name, ty.clone(), true),
+                    pattern => self.bind(pattern, ty.clone()),
+                },
+                pattern => self.bind(pattern, Type::Unknown),
+            }
+        }
+        self.functions.push(FunctionContext {
+            declared: declared.clone(),
+            returned: vec![],
+        });
+        self.block(body);
+        let function = self.functions.pop().unwrap();
+        self.scopes.pop();
+        self.pos = pos;
+
+        if let Some(declared) = declared {
+            return declared.clone();
+        }
+        let ends_with_return = matches!(
+            body.last().map(Statement::unlocated),
+            Some(Statement::Return(_))
+        );
+        match function.returned.into_iter().reduce(Type::union) {
+            Some(returns) if ends_with_return => returns,
+            None => Type::None,
+            Some(_) => Type::Unknown,
+        }
+    }
+
+    /// Bind the pattern of a `for` loop or a comprehension. Iterating over a range gives numbers.
+    fn for_head(&mut self, pattern: &Pattern, iterable: &Expr) {
+        let item = match self.expr(iterable) {
+            Type::Range => Type::Number,
+            _ => Type::Unknown,
+        };
+        self.bind(pattern, item);
+    }
+
+    /// Check the arms of a match, returning the type of the value it evaluates to
+    fn match_arms(&mut self, subject: &Expr, arms: &[MatchArm]) -> Type {
+        self.expr(subject);
+        let mut ty: Option<Type> = None;
+        for arm in arms {
+            self.scopes.push(HashMap::new());
+            self.bind(&arm.pattern, Type::Unknown);
+            if let Some(guard) = &arm.guard {
+                self.condition(guard);
+            }
+            let arm_type = self.block(&arm.body);
+            self.scopes.pop();
+            ty = Some(match ty {
+                Some(ty) => ty.union(arm_type),
+                None => arm_type,
+            });
+        }
+        ty.unwrap_or(Type::None)
+    }
+
+    fn comprehension(&mut self, comprehension: &Comprehension) {
+        self.for_head(&comprehension.pattern, &comprehension.iterable);
+        if let Some(condition) = &comprehension.condition {
+            self.condition(condition);
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Type {
+        match expr {
+            Expr::Located { pos, expr } => {
+                let outer = std::mem::replace(&mut self.pos, *pos);
+                let ty = self.expr(expr);
+                self.pos = outer;
+                ty
+            }
+            Expr::Number(_) => Type::Number,
+            Expr::String(_) => Type::String,
+            Expr::Boolean(_) => Type::Boolean,
+            Expr::Identifier(name) => self
+                .lookup(name)
+                .map_or(Type::Unknown, |variable| variable.ty.clone()),
+            Expr::List(items) => {
+                for item in items {
+                    self.expr(item);
+                }
+                Type::List
+            }
+            Expr::Tuple(items) => {
+                for item in items {
+                    self.expr(item);
+                }
+                Type::Tuple
+            }
+            Expr::Map(entries) => {
+                for (key, value) in entries {
+                    self.expr(key);
+                    self.expr(value);
+                }
+                Type::Map
+            }
+            Expr::Acessor(parts) => {
+                // The members are names, only the root is an expression
+                if let Some(root) = parts.first() {
+                    self.expr(root);
+                }
+                Type::Unknown
+            }
+            Expr::Call(callee, args) => self.call(callee, args),
+            Expr::BinaryOp { op, lhs, rhs } => {
+                let lhs = self.expr(lhs);
+                let rhs = self.expr(rhs);
+                self.binary_op(op, lhs, rhs)
+            }
+            Expr::Range { start, end, .. } => {
+                let start = self.expr(start);
+                self.expect(&Type::Number, &start);
+                let end = self.expr(end);
+                self.expect(&Type::Number, &end);
+                Type::Range
+            }
+            Expr::Match { subject, arms } => self.match_arms(subject, arms),
+            Expr::If {
+                condition,
+                body,
+                else_body,
+            } => {
+                self.condition(condition);
+                let body = self.block(body);
+                let else_body = self.block(else_body);
+                body.union(else_body)
+            }
+            Expr::ListComprehension {
+                element,
+                comprehension,
+            } => {
+                self.scopes.push(HashMap::new());
+                self.comprehension(comprehension);
+                self.expr(element);
+                self.scopes.pop();
+                Type::List
+            }
+            Expr::MapComprehension {
+                key,
+                value,
+                comprehension,
+            } => {
+                self.scopes.push(HashMap::new());
+                self.comprehension(comprehension);
+                self.expr(key);
+                self.expr(value);
+                self.scopes.pop();
+                Type::Map
+            }
+            Expr::Generator {
+                element,
+                comprehension,
+            } => {
+                self.scopes.push(HashMap::new());
+                self.comprehension(comprehension);
+                self.expr(element);
+                self.scopes.pop();
+                Type::Unknown
+            }
+            Expr::Block(statements) => self.block(statements),
+            Expr::Is { value, .. } => {
+                self.expr(value);
+                Type::Boolean
+            }
+        }
+    }
+
+    fn call(&mut self, callee: &Expr, args: &[Expr]) -> Type {
+        let callee = self.expr(callee);
+        let args: Vec<Type> = args.iter().map(|arg| self.expr(arg)).collect();
+        match callee {
+            Type::Function { params, returns } => {
+                if params.len() != args.len() {
+                    self.error(ErrorType::WrongArgumentCount {
+                        expected: params.len(),
+                        got: args.len(),
+                    });
+                } else {
+                    for (param, arg) in params.iter().zip(&args) {
+                        self.expect(param, arg);
+                    }
+                }
+                *returns
+            }
+            Type::Unknown => Type::Unknown,
+            other => {
+                self.error(ErrorType::NotCallable(other.to_string()));
+                Type::Unknown
+            }
+        }
+    }
+
+    /// The type of a binary operation, reporting the operands the builtin classes don't support
+    fn binary_op(&mut self, op: &Token, lhs: Type, rhs: Type) -> Type {
+        let result = match (op, &lhs, &rhs) {
+            (Token::Equal | Token::NotEqual, _, _) => return Type::Boolean,
+            (_, Type::Unknown, _) | (_, _, Type::Unknown) => {
+                return match op {
+                    Token::GreaterThan | Token::LessThan => Type::Boolean,
+                    _ => Type::Unknown,
+                }
+            }
+            (Token::Plus, Type::Number, Type::Number) => Some(Type::Number),
+            (Token::Plus, Type::String, Type::String) => Some(Type::String),
+            (Token::Plus, Type::List, Type::List) => Some(Type::List),
+            (
+                Token::Minus | Token::Star | Token::Divider | Token::Modulo,
+                Type::Number,
+                Type::Number,
+            ) => Some(Type::Number),
+            (Token::GreaterThan | Token::LessThan, Type::Number, Type::Number) => {
+                Some(Type::Boolean)
+            }
+            _ => None,
+        };
+        result.unwrap_or_else(|| {
+            self.error(ErrorType::InvalidOperands {
+                operator: operator(op).to_string(),
+                lhs: lhs.to_string(),
+                rhs: rhs.to_string(),
+            });
+            Type::Unknown
+        })
+    }
+}
+
+/// How a binary operator is written
+fn operator(op: &Token) -> &'static str {
+    match op {
+        Token::Plus => "+",
+        Token::Minus => "-",
+        Token::Star => "*",
+        Token::Divider => "/",
+        Token::Modulo => "%",
+        Token::GreaterThan => ">",
+        Token::LessThan => "<",
+        Token::Equal => "==",
+        Token::NotEqual => "!=",
+        _ => "?",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn errors(input: &str) -> Vec<ErrorType> {
+        check(input)
+            .expect("Failed to parse")
+            .iter()
+            .map(|error| error.error_type().clone())
+            .collect()
+    }
+
+    fn mismatch(expected: &str, found: &str) -> ErrorType {
+        ErrorType::TypeMismatch {
+            expected: expected.into(),
+            found: found.into(),
+        }
+    }
+
+    #[test]
+    fn test_check_valid_program() {
+        let program = "
+            fn add(a: Number, b: Number) -> Number { return a + b }
+            fn greet(name) { return \"Hello \" + name }
+            x: String = greet(\"you\")
+            total = add(1, 2) * 3
+            for i in 0..total { total = total + i }
+            if total > 3 { y = [1] + [2] }
+        ";
+        assert_eq!(errors(program), vec![]);
+    }
+
+    #[test]
+    fn test_check_annotations() {
+        assert_eq!(errors("x: String = 1"), vec![mismatch("String", "Number")]);
+        assert_eq!(
+            errors("x: Number = 1 x = \"a\""),
+            vec![mismatch("Number", "String")]
+        );
+        assert_eq!(
+            errors("fn f() -> Number { return \"a\" }"),
+            vec![mismatch("Number", "String")]
+        );
+        assert_eq!(
+            errors("x: Integer = 1"),
+            vec![ErrorType::UnknownType("Integer".into())]
+        );
+        assert_eq!(errors("enum Color { Red } c: Color = Color.Red"), vec![]);
+        assert_eq!(
+            errors(
+                "class Money(amount) {
+                    fn add(self, other: Money) -> Money { return Money(self.amount + other.amount) }
+                    fn label(self) -> String { return 1 }
+                }
+                m: Money = Money(1)"
+            ),
+            vec![mismatch("String", "Number")]
+        );
+        assert_eq!(
+            errors("a: Number, b: String = 1, 2 b = 3"),
+            vec![mismatch("String", "Number")]
+        );
+    }
+
+    #[test]
+    fn test_check_inference() {
+        assert_eq!(
+            errors("x = 1 y = x + \"a\""),
+            vec![ErrorType::InvalidOperands {
+                operator: "+".into(),
+                lhs: "Number".into(),
+                rhs: "String".into(),
+            }]
+        );
+        assert_eq!(errors("if 1 { }"), vec![mismatch("Boolean", "Number")]);
+        // The return type is inferred from the body
+        assert_eq!(
+            errors("fn name() { return \"a\" } n: Number = name()"),
+            vec![mismatch("Number", "String")]
+        );
+        // A variable given values of different types can hold any of them
+        assert_eq!(errors("x = 1 if true { x = \"a\" } y = x + 1"), vec![]);
+    }
+
+    #[test]
+    fn test_check_calls() {
+        assert_eq!(
+            errors("fn add(a: Number, b: Number) { return a + b } add(1, \"2\")"),
+            vec![mismatch("Number", "String")]
+        );
+        assert_eq!(
+            errors("fn add(a, b) { return a + b } add(1)"),
+            vec![ErrorType::WrongArgumentCount {
+                expected: 2,
+                got: 1
+            }]
+        );
+        assert_eq!(
+            errors("x = 1 x()"),
+            vec![ErrorType::NotCallable("Number".into())]
+        );
+    }
+
+    #[test]
+    fn test_check_error_positions() {
+        let errors = check("x = 1\ny: String = x").unwrap();
+        assert_eq!(errors.len(), 1);
+        assert!(format!("{:?}", errors[0]).contains("at 2:1"));
+    }
+}
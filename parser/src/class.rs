@@ -0,0 +1,359 @@
+use crate::{
+    errors::{self, Error},
+    expr::Expr,
+    pattern::Pattern,
+    statement::Statement,
+    tokens, Parser,
+};
+
+/// A method declared in a class body, like `fn compare(self, other) { ... }`.
+/// The instance is passed explicitly as the first parameter, except for static methods.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Method {
+    pub kind: MethodKind,
+    pub name: String,
+    pub params: Vec<Pattern>,
+    pub return_type: Option<String>,
+    pub body: Vec<Statement>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum MethodKind {
+    /// `fn name(self, ...)`, called on an instance
+    Instance,
+    /// `static fn name(...)`, called on the class: `Money.zero()`
+    Static,
+    /// `get name(self)`, called to read the property `name` of an instance
+    Getter,
+    /// `set name(self, value)`, called to assign the property `name` of an instance
+    Setter,
+}
+
+/// A method an interface requires, like `fn compare(self, other)`, without a body
+#[derive(Debug, PartialEq, Clone)]
+pub struct MethodSignature {
+    pub name: String,
+    pub params: Vec<Pattern>,
+    pub return_type: Option<String>,
+}
+
+impl Parser {
+    /// Parse a class declaration, the current token being `class`:
+    /// `class Money(amount, currency) implements Comparable { fn compare(self, other) { ... } }`.
+    /// Besides methods, the body can declare properties (`get name(self)`, `set name(self, value)`),
+    /// static methods (`static fn zero()`) and static fields (`static RATE = 1.1`).
+    pub fn parse_class(&mut self) -> Result<Statement, Error> {
+        self.next_token();
+        let name = self.expect_identifier()?;
+
+        let mut fields = Vec::new();
+        if self.current_token == Some(tokens::Token::LParen) {
+            self.next_token();
+            while self.current_token != Some(tokens::Token::RParen) {
+                let field = self.expect_identifier()?;
+                if fields.contains(&field) {
+                    return Err(self.error(errors::ErrorType::Redeclaration(field)));
+                }
+                fields.push(field);
+                self.expect_comma_or(&tokens::Token::RParen)?;
+            }
+            self.next_token();
+        }
+
+        let mut interfaces = Vec::new();
+        if self.current_token == Some(tokens::Token::Identifier("implements".into())) {
+            self.next_token();
+            interfaces.push(self.expect_identifier()?);
+            while self.current_token == Some(tokens::Token::Comma) {
+                self.next_token();
+                interfaces.push(self.expect_identifier()?);
+            }
+        }
+
+        self.expect(tokens::Token::LBrace)?;
+        let mut methods: Vec<Method> = Vec::new();
+        let mut statics: Vec<(String, Expr)> = Vec::new();
+        while self.current_token != Some(tokens::Token::RBrace) {
+            let keyword = match &self.current_token {
+                Some(tokens::Token::Identifier(keyword)) => keyword.clone(),
+                _ => String::new(),
+            };
+            let kind = match keyword.as_str() {
+                "fn" => MethodKind::Instance,
+                "get" => MethodKind::Getter,
+                "set" => MethodKind::Setter,
+                "static" if self.next_token == Some(tokens::Token::Identifier("fn".into())) => {
+                    self.next_token();
+                    MethodKind::Static
+                }
+                "static" => {
+                    self.next_token();
+                    let name = self.expect_identifier()?;
+                    if statics.iter().any(|(field, _)| *field == name)
+                        || methods
+                            .iter()
+                            .any(|method| method.kind == MethodKind::Static && method.name == name)
+                    {
+                        return Err(self.error(errors::ErrorType::Redeclaration(name)));
+                    }
+                    self.expect(tokens::Token::Assign)?;
+                    statics.push((name, self.parse_expr()?));
+                    continue;
+                }
+                _ => {
+                    return Err(self.error(errors::ErrorType::ExpectedToken(
+                        tokens::Token::Identifier("fn".into()),
+                    )))
+                }
+            };
+            let Statement::Fn {
+                name,
+                params,
+                return_type,
+                body,
+            } = self.parse_fn()?
+            else {
+                unreachable!("parse_fn returns a function declaration")
+            };
+            let expected_params = match kind {
+                MethodKind::Getter => Some(1),
+                MethodKind::Setter => Some(2),
+                _ => None,
+            };
+            if let Some(expected) = expected_params.filter(|n| *n != params.len()) {
+                return Err(self.error(errors::ErrorType::PropertyParameters { name, expected }));
+            }
+            let clashes = |other: &Method| {
+                other.name == name
+                    && match (kind, other.kind) {
+                        (MethodKind::Static, other) => other == MethodKind::Static,
+                        (_, MethodKind::Static) => false,
+                        // A property can have both a getter and a setter
+                        (MethodKind::Getter, MethodKind::Setter)
+                        | (MethodKind::Setter, MethodKind::Getter) => false,
+                        _ => true,
+                    }
+            };
+            let is_field = kind != MethodKind::Static && fields.contains(&name);
+            let is_static_field =
+                kind == MethodKind::Static && statics.iter().any(|(field, _)| *field == name);
+            if methods.iter().any(clashes) || is_field || is_static_field {
+                return Err(self.error(errors::ErrorType::Redeclaration(name)));
+            }
+            methods.push(Method {
+                kind,
+                name,
+                params,
+                return_type,
+                body,
+            });
+        }
+        self.next_token();
+
+        Ok(Statement::Class {
+            name,
+            fields,
+            interfaces,
+            methods,
+            statics,
+        })
+    }
+
+    /// Parse an interface declaration, the current token being `interface`:
+    /// `interface Comparable { fn compare(self, other) }`
+    pub fn parse_interface(&mut self) -> Result<Statement, Error> {
+        self.next_token();
+        let name = self.expect_identifier()?;
+        self.expect(tokens::Token::LBrace)?;
+
+        let mut methods: Vec<MethodSignature> = Vec::new();
+        while self.current_token != Some(tokens::Token::RBrace) {
+            if self.current_token != Some(tokens::Token::Identifier("fn".into())) {
+                return Err(self.error(errors::ErrorType::ExpectedToken(
+                    tokens::Token::Identifier("fn".into()),
+                )));
+            }
+            self.next_token();
+            let method = self.expect_identifier()?;
+            if methods.iter().any(|signature| signature.name == method) {
+                return Err(self.error(errors::ErrorType::Redeclaration(method)));
+            }
+            self.expect(tokens::Token::LParen)?;
+            let params = self.parse_pattern_list(tokens::Token::RParen)?;
+            let return_type = if self.current_token == Some(tokens::Token::Arrow) {
+                self.next_token();
+                Some(self.expect_identifier()?)
+            } else {
+                None
+            };
+            methods.push(MethodSignature {
+                name: method,
+                params,
+                return_type,
+            });
+        }
+        self.next_token();
+
+        Ok(Statement::Interface { name, methods })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::ErrorType;
+
+    fn binding(name: &str) -> Pattern {
+        Pattern::Binding(name.into())
+    }
+
+    #[test]
+    fn test_parse_class() {
+        assert_eq!(
+            Parser::parse(
+                "class Money(amount, currency) implements Comparable, Show {
+                    fn compare(self, other) { return self.amount - other.amount }
+                }"
+            ),
+            Ok(Statement::Block(vec![Statement::Class {
+                name: "Money".into(),
+                fields: vec!["amount".into(), "currency".into()],
+                interfaces: vec!["Comparable".into(), "Show".into()],
+                methods: vec![Method {
+                    kind: MethodKind::Instance,
+                    name: "compare".into(),
+                    params: vec![binding("self"), binding("other")],
+                    return_type: None,
+                    body: vec![Statement::Return(Expr::BinaryOp {
+                        op: tokens::Token::Minus,
+                        lhs: Box::new(Expr::Acessor(vec![
+                            Expr::Identifier("self".into()),
+                            Expr::Identifier("amount".into()),
+                        ])),
+                        rhs: Box::new(Expr::Acessor(vec![
+                            Expr::Identifier("other".into()),
+                            Expr::Identifier("amount".into()),
+                        ])),
+                    })],
+                }],
+                statics: vec![],
+            }]))
+        );
+        assert_eq!(
+            Parser::parse("class Empty {}"),
+            Ok(Statement::Block(vec![Statement::Class {
+                name: "Empty".into(),
+                fields: vec![],
+                interfaces: vec![],
+                methods: vec![],
+                statics: vec![],
+            }]))
+        );
+        assert_eq!(
+            Parser::parse("class A(x) { fn x(self) {} }")
+                .unwrap_err()
+                .error_type(),
+            &ErrorType::Redeclaration("x".into())
+        );
+    }
+
+    #[test]
+    fn test_parse_class_members() {
+        let Ok(Statement::Block(statements)) = Parser::parse(
+            "class Circle(radius) {
+                static RATE = 1.5
+                static fn unit() { return Circle(1) }
+                get diameter(self) { return self.radius * 2 }
+                set diameter(self, value) { self.radius = value / 2 }
+            }",
+        ) else {
+            panic!("Failed to parse the class");
+        };
+        let Statement::Class {
+            methods, statics, ..
+        } = &statements[0]
+        else {
+            panic!("Expected a class");
+        };
+        assert_eq!(statics, &vec![("RATE".to_string(), Expr::Number(1.5))]);
+        let kinds: Vec<_> = methods
+            .iter()
+            .map(|method| (method.kind, method.name.as_str()))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (MethodKind::Static, "unit"),
+                (MethodKind::Getter, "diameter"),
+                (MethodKind::Setter, "diameter"),
+            ]
+        );
+        assert_eq!(
+            methods[2].body,
+            vec![Statement::SetField {
+                object: Expr::Identifier("self".into()),
+                field: "radius".into(),
+                value: Expr::BinaryOp {
+                    op: tokens::Token::Divider,
+                    lhs: Box::new(Expr::Identifier("value".into())),
+                    rhs: Box::new(Expr::Number(2.0)),
+                },
+            }]
+        );
+
+        // Static members live on the class, so they can share a name with the members of the instances
+        assert!(
+            Parser::parse("class A(x) { static x = 1 static fn f() {} fn f(self) {} }").is_ok()
+        );
+        assert_eq!(
+            Parser::parse("class A { get x(self) {} get x(self) {} }")
+                .unwrap_err()
+                .error_type(),
+            &ErrorType::Redeclaration("x".into())
+        );
+        assert_eq!(
+            Parser::parse("class A { set x(value) {} }")
+                .unwrap_err()
+                .error_type(),
+            &ErrorType::PropertyParameters {
+                name: "x".into(),
+                expected: 2
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_interface() {
+        assert_eq!(
+            Parser::parse(
+                "interface Comparable { fn compare(self, other) -> Number fn max(self) }"
+            ),
+            Ok(Statement::Block(vec![Statement::Interface {
+                name: "Comparable".into(),
+                methods: vec![
+                    MethodSignature {
+                        name: "compare".into(),
+                        params: vec![binding("self"), binding("other")],
+                        return_type: Some("Number".into()),
+                    },
+                    MethodSignature {
+                        name: "max".into(),
+                        params: vec![binding("self")],
+                        return_type: None,
+                    },
+                ],
+            }]))
+        );
+    }
+
+    #[test]
+    fn test_parse_is() {
+        assert_eq!(
+            Parser::parse("x is Comparable"),
+            Ok(Statement::Block(vec![Statement::Expr(Expr::Is {
+                value: Box::new(Expr::Identifier("x".into())),
+                class: "Comparable".into(),
+            })]))
+        );
+    }
+}
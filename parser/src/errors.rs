@@ -1,15 +1,44 @@
 use std::fmt::Debug;
 
-use colored::Colorize;
+use colored::{ColoredString, Colorize};
 
 use crate::tokens::Token;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum ErrorType {
     UnexpectedToken(Token),
     UnexpectedEndOfFile,
     UnClosedParenthesis,
     ExpectedToken(Token),
+    AssignToConstant(String),
+    Redeclaration(String),
+    InvalidPipelineTarget,
+    UnknownMacro(String),
+    MacroArity {
+        name: String,
+        expected: usize,
+        got: usize,
+    },
+    MacroArgumentNotAName(String),
+    TypeMismatch {
+        expected: String,
+        found: String,
+    },
+    InvalidOperands {
+        operator: String,
+        lhs: String,
+        rhs: String,
+    },
+    WrongArgumentCount {
+        expected: usize,
+        got: usize,
+    },
+    NotCallable(String),
+    UnknownType(String),
+    PropertyParameters {
+        name: String,
+        expected: usize,
+    },
 }
 
 impl ErrorType {
@@ -19,6 +48,42 @@ impl ErrorType {
             ErrorType::UnexpectedEndOfFile => "Unexpected end of file".to_string(),
             ErrorType::UnClosedParenthesis => "Unclosed parenthesis".to_string(),
             ErrorType::ExpectedToken(token) => format!("Expected token: {:?}", token),
+            ErrorType::AssignToConstant(name) => {
+                format!("Cannot assign to constant '{}'", name)
+            }
+            ErrorType::Redeclaration(name) => {
+                format!("'{}' is already declared in this scope", name)
+            }
+            ErrorType::InvalidPipelineTarget => {
+                "The right side of `|>` must be a function or a call".to_string()
+            }
+            ErrorType::UnknownMacro(name) => format!("Macro '{}' is not declared", name),
+            ErrorType::MacroArity {
+                name,
+                expected,
+                got,
+            } => format!(
+                "Macro '{}' expects {} arguments, got {}",
+                name, expected, got
+            ),
+            ErrorType::MacroArgumentNotAName(param) => format!(
+                "The argument given for '{}' must be a name, since the macro declares it",
+                param
+            ),
+            ErrorType::TypeMismatch { expected, found } => {
+                format!("Expected a value of type {}, found {}", expected, found)
+            }
+            ErrorType::InvalidOperands { operator, lhs, rhs } => {
+                format!("Cannot apply `{}` to {} and {}", operator, lhs, rhs)
+            }
+            ErrorType::WrongArgumentCount { expected, got } => {
+                format!("Expected {} arguments, got {}", expected, got)
+            }
+            ErrorType::NotCallable(ty) => format!("A value of type {} cannot be called", ty),
+            ErrorType::UnknownType(name) => format!("Unknown type '{}'", name),
+            ErrorType::PropertyParameters { name, expected } => {
+                format!("Property '{}' must take {} parameters", name, expected)
+            }
         }
     }
 }
@@ -38,37 +103,88 @@ impl Error {
             input,
         }
     }
+
+    pub fn error_type(&self) -> &ErrorType {
+        &self.error_type
+    }
+
+    /// The message of the error, without the input it points at
+    pub fn message(&self) -> String {
+        self.error_type.message()
+    }
+
+    /// The position in the input where the error was found
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
 }
 
 impl Debug for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let line_number = self.input[..self.pos].lines().count();
-        let column_number = self.input[..self.pos]
-            .lines()
-            .last()
-            .map_or(0, |line| line.len());
-
-        let mut error_message = format!(
-            "{} at {}:{}\n",
-            self.error_type.message().red().bold(),
-            line_number,
-            column_number
-        );
-
-        let input_line = self
-            .input
-            .lines()
-            .nth(line_number - 1)
-            .unwrap_or(&self.input);
-        let marker_line = "-".repeat(column_number - 1);
-
-        error_message.push_str(&format!(
-            "| {}\n| {}{}",
-            input_line,
-            marker_line.blue().bold(),
-            "^".red().bold()
-        ));
-
-        write!(f, "{}", error_message)
+        let message = self.error_type.message().red().bold();
+        write!(f, "{}", render(message, self.pos, &self.input))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum WarningType {
+    NonExhaustiveMatch,
+}
+
+impl WarningType {
+    fn message(&self) -> String {
+        match self {
+            WarningType::NonExhaustiveMatch => {
+                "Match is not exhaustive, add a `_` arm to handle the other values".to_string()
+            }
+        }
+    }
+}
+
+/// A warning doesn't stop the parsing, but points at code that is probably wrong.
+#[derive(PartialEq)]
+pub struct Warning {
+    warning_type: WarningType,
+    pos: usize,
+    input: String,
+}
+
+impl Warning {
+    pub fn new(warning_type: WarningType, pos: usize, input: String) -> Self {
+        Self {
+            warning_type,
+            pos,
+            input,
+        }
+    }
+}
+
+impl Debug for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let message = self.warning_type.message().yellow().bold();
+        write!(f, "{}", render(message, self.pos, &self.input))
     }
 }
+
+/// Render a message along with the line of the input it points at
+fn render(message: ColoredString, pos: usize, input: &str) -> String {
+    let line_number = input[..pos].lines().count();
+    let column_number = input[..pos].lines().last().map_or(0, |line| line.len());
+
+    let mut error_message = format!("{} at {}:{}\n", message, line_number, column_number);
+
+    let input_line = input
+        .lines()
+        .nth(line_number.saturating_sub(1))
+        .unwrap_or(input);
+    let marker_line = "-".repeat(column_number.saturating_sub(1));
+
+    error_message.push_str(&format!(
+        "| {}\n| {}{}",
+        input_line,
+        marker_line.blue().bold(),
+        "^".red().bold()
+    ));
+
+    error_message
+}
@@ -1,6 +1,8 @@
 use crate::Parser;
 use crate::{
     errors::{self, Error},
+    pattern::{MatchArm, Pattern},
+    statement::Statement,
     tokens,
 };
 
@@ -10,6 +12,12 @@ pub enum Expr {
     Boolean(bool),
     Identifier(String),
     String(String),
+    /// A list literal, like `[1, 2, 3]`
+    List(Vec<Expr>),
+    /// A map literal, like `{"a": 1, "b": 2}`
+    Map(Vec<(Expr, Expr)>),
+    /// A tuple, like `(1, "a")`. The parentheses can be left out after `=` and `return`, as in `return a, b`
+    Tuple(Vec<Expr>),
     /// An Accessor is a way to access a value in a data structure.
     /// For example, in the expression `a.b.c`, `a` is the root, `b` is the first accessor, and `c` is the second accessor.
     /// We use expressions to represent accessors because they can also be used with numbers and other expressions. (e.g. `10.floor`)
@@ -23,6 +31,83 @@ pub enum Expr {
         lhs: Box<Expr>,
         rhs: Box<Expr>,
     },
+    /// A range of numbers, `start..end` or `start..=end` when `end` is included
+    Range {
+        start: Box<Expr>,
+        end: Box<Expr>,
+        inclusive: bool,
+    },
+    /// A match used as a value, like `x = match n { 0 => "zero", _ => "other" }`.
+    /// It evaluates to the value of the last expression of the matching arm, or None if no arm matches.
+    Match {
+        subject: Box<Expr>,
+        arms: Vec<MatchArm>,
+    },
+    /// An if used as a value, like `x = if a > b { a } else { b }` or `x = a if a > b else b`.
+    /// It evaluates to the value of the last expression of the chosen branch, or None if there is no `else`.
+    If {
+        condition: Box<Expr>,
+        body: Vec<Statement>,
+        else_body: Vec<Statement>,
+    },
+    /// A list built from another collection, like `[x * 2 for x in xs if x > 0]`
+    ListComprehension {
+        element: Box<Expr>,
+        comprehension: Box<Comprehension>,
+    },
+    /// A map built from another collection, like `{k: v for k, v in pairs}`
+    MapComprehension {
+        key: Box<Expr>,
+        value: Box<Expr>,
+        comprehension: Box<Comprehension>,
+    },
+    /// A comprehension in parentheses, like `(x * 2 for x in xs)`.
+    /// Its elements are only computed while it is iterated over.
+    Generator {
+        element: Box<Expr>,
+        comprehension: Box<Comprehension>,
+    },
+    /// A check of the class of a value, like `x is Number` or `x is Comparable`.
+    /// With an interface, it checks that the value has the methods of the interface.
+    Is {
+        value: Box<Expr>,
+        class: String,
+    },
+    /// Statements used as a value, evaluating to the last expression among them.
+    /// They come from the expansion of a macro called where a value is expected.
+    Block(Vec<Statement>),
+    /// A call with the position where it starts in the input, so the errors it raises point at it.
+    /// The parser only wraps the calls with their position when `Parser::positions` is set.
+    Located {
+        pos: usize,
+        expr: Box<Expr>,
+    },
+}
+
+impl Expr {
+    /// The expression, without the position wrapping it if there is one
+    pub fn unlocated(&self) -> &Expr {
+        match self {
+            Expr::Located { expr, .. } => expr.unlocated(),
+            expr => expr,
+        }
+    }
+
+    /// Take the expression out of the position wrapping it if there is one
+    pub fn into_unlocated(self) -> Expr {
+        match self {
+            Expr::Located { expr, .. } => expr.into_unlocated(),
+            expr => expr,
+        }
+    }
+}
+
+/// The `for pattern in iterable if condition` part of a comprehension
+#[derive(Debug, PartialEq, Clone)]
+pub struct Comprehension {
+    pub pattern: Pattern,
+    pub iterable: Box<Expr>,
+    pub condition: Option<Box<Expr>>,
 }
 
 /// This impl block group the expression parsing functions together
@@ -31,6 +116,17 @@ pub enum Expr {
 /// But "let x = ..." is a statement, not an expression, because it doesn't produce a value.
 impl Parser {
     // Utilities
+    /// Wrap an expression starting at `pos` with its position, if the parser locates what it parses
+    fn located(&self, pos: usize, expr: Expr) -> Expr {
+        match self.positions {
+            true => Expr::Located {
+                pos,
+                expr: Box::new(expr),
+            },
+            false => expr,
+        }
+    }
+
     fn binary_op(&mut self, op: tokens::Token, lhs: Expr, rhs: Expr) -> Expr {
         Expr::BinaryOp {
             op,
@@ -41,35 +137,116 @@ impl Parser {
 
     // Parsing functions
     pub fn parse_expr(&mut self) -> Result<Expr, Error> {
-        let mut lhs = self.parse_add_sub()?;
+        let value = self.parse_pipeline()?;
+        if self.current_token != Some(tokens::Token::Identifier("if".into())) {
+            return Ok(value);
+        }
+
+        // `value if condition else other`. Without the `else`, the `if` starts a new statement
+        let checkpoint = self.checkpoint();
+        self.next_token();
+        let condition = match self.parse_pipeline() {
+            Ok(condition)
+                if self.current_token == Some(tokens::Token::Identifier("else".into())) =>
+            {
+                condition
+            }
+            _ => {
+                self.restore(checkpoint);
+                return Ok(value);
+            }
+        };
+        self.next_token();
+        let other = self.parse_expr()?;
+        Ok(Expr::If {
+            condition: Box::new(condition),
+            body: vec![Statement::Expr(value)],
+            else_body: vec![Statement::Expr(other)],
+        })
+    }
+
+    /// `x |> f(a)` is the same as `f(x, a)`, so `load(path) |> filter() |> save()` reads in the order it runs
+    pub fn parse_pipeline(&mut self) -> Result<Expr, Error> {
+        let mut lhs = self.parse_comparison()?;
+        while self.current_token == Some(tokens::Token::PipeArrow) {
+            self.next_token();
+            let target_start = self.token_pos;
+            let target_pos = self.pos;
+            let call = match self.parse_call()?.into_unlocated() {
+                Expr::Call(function, mut args) => {
+                    args.insert(0, lhs);
+                    Expr::Call(function, args)
+                }
+                function @ (Expr::Identifier(_) | Expr::Acessor(_)) => {
+                    Expr::Call(Box::new(function), vec![lhs])
+                }
+                _ => {
+                    return Err(Error::new(
+                        errors::ErrorType::InvalidPipelineTarget,
+                        target_pos,
+                        self.input.clone(),
+                    ))
+                }
+            };
+            lhs = self.located(target_start, call);
+        }
+        Ok(lhs)
+    }
+
+    pub fn parse_comparison(&mut self) -> Result<Expr, Error> {
+        let mut lhs = self.parse_range()?;
         while let Some(op) = &self.current_token {
             match op {
                 tokens::Token::Equal => {
                     self.next_token();
-                    let rhs = self.parse_expr()?;
+                    let rhs = self.parse_comparison()?;
                     lhs = self.binary_op(tokens::Token::Equal, lhs, rhs);
                 }
                 tokens::Token::NotEqual => {
                     self.next_token();
-                    let rhs = self.parse_expr()?;
+                    let rhs = self.parse_comparison()?;
                     lhs = self.binary_op(tokens::Token::NotEqual, lhs, rhs);
                 }
                 tokens::Token::LessThan => {
                     self.next_token();
-                    let rhs = self.parse_expr()?;
+                    let rhs = self.parse_comparison()?;
                     lhs = self.binary_op(tokens::Token::LessThan, lhs, rhs);
                 }
                 tokens::Token::GreaterThan => {
                     self.next_token();
-                    let rhs = self.parse_expr()?;
+                    let rhs = self.parse_comparison()?;
                     lhs = self.binary_op(tokens::Token::GreaterThan, lhs, rhs);
                 }
+                tokens::Token::Identifier(keyword) if keyword == "is" => {
+                    self.next_token();
+                    lhs = Expr::Is {
+                        value: Box::new(lhs),
+                        class: self.expect_identifier()?,
+                    };
+                }
                 _ => break,
             }
         }
         Ok(lhs)
     }
 
+    /// Ranges have a lower precedence than arithmetic, so `0..n + 1` goes up to `n + 1`
+    pub fn parse_range(&mut self) -> Result<Expr, Error> {
+        let start = self.parse_add_sub()?;
+        let inclusive = match self.current_token {
+            Some(tokens::Token::DotDot) => false,
+            Some(tokens::Token::DotDotEq) => true,
+            _ => return Ok(start),
+        };
+        self.next_token();
+        let end = self.parse_add_sub()?;
+        Ok(Expr::Range {
+            start: Box::new(start),
+            end: Box::new(end),
+            inclusive,
+        })
+    }
+
     pub fn parse_add_sub(&mut self) -> Result<Expr, Error> {
         let mut lhs = self.parse_term()?;
         while let Some(op) = &self.current_token {
@@ -116,29 +293,38 @@ impl Parser {
     }
 
     pub fn parse_call(&mut self) -> Result<Expr, Error> {
+        let start = self.token_pos;
         let mut lhs = self.parse_accessors()?;
-        while let Some(tokens::Token::LParen) = &self.current_token {
+        // A parenthesis starting a new line starts a new statement, like `(x, y) = (y, x)`
+        while self.current_token == Some(tokens::Token::LParen) && !self.newline_before {
             self.next_token();
             let mut args = Vec::new();
             while self.current_token != Some(tokens::Token::RParen) {
-                args.push(self.parse_expr()?);
+                let arg = self.parse_expr()?;
+                // A generator can be passed without its own parentheses: `sum(x for x in xs)`
+                if args.is_empty() && self.at_comprehension() {
+                    args.push(Expr::Generator {
+                        element: Box::new(arg),
+                        comprehension: Box::new(self.parse_comprehension()?),
+                    });
+                    continue;
+                }
+                args.push(arg);
                 if self.current_token == Some(tokens::Token::Comma) {
                     self.next_token();
                 }
             }
             self.next_token();
-            lhs = Expr::Call(Box::new(lhs), args);
+            lhs = self.located(start, Expr::Call(Box::new(lhs), args));
         }
         if let Some(tokens::Token::Point) = &self.current_token {
             self.next_token();
             let rhs = self.parse_call()?;
-            if let Expr::Call(i, args) = rhs.clone() {
-                if let Expr::Call(i2, args2) = lhs.clone() {
-                    lhs = Expr::Call(Box::new(Expr::Acessor(vec![lhs, *i])), args);
-
-                } else {
-                    lhs = Expr::Acessor(vec![lhs, rhs]);
-                }
+            if let (Expr::Call(i, args), Expr::Call(_, _)) =
+                (rhs.unlocated().clone(), lhs.unlocated())
+            {
+                let method = Expr::Acessor(vec![lhs, *i]);
+                lhs = self.located(start, Expr::Call(Box::new(method), args));
             } else {
                 lhs = Expr::Acessor(vec![lhs, rhs]);
             }
@@ -171,6 +357,17 @@ impl Parser {
                 let s = s.clone();
                 Ok(Expr::String(s))
             }
+            Some(tokens::Token::Identifier(id)) if id == "if" => self.parse_if_expr(),
+            Some(tokens::Token::Identifier(_)) if self.next_token == Some(tokens::Token::Bang) => {
+                self.parse_macro_call_expr()
+            }
+            Some(tokens::Token::Identifier(id)) if id == "match" => {
+                let (subject, arms) = self.parse_match()?;
+                Ok(Expr::Match {
+                    subject: Box::new(subject),
+                    arms,
+                })
+            }
             Some(tokens::Token::Identifier(id)) => {
                 self.next_token();
                 let id = id.clone();
@@ -181,6 +378,8 @@ impl Parser {
                 }
             }
             Some(tokens::Token::LParen) => self.parse_paren(),
+            Some(tokens::Token::LBracket) => self.parse_list(),
+            Some(tokens::Token::LBrace) => self.parse_map(),
             None => Err(self.error(errors::ErrorType::UnexpectedEndOfFile)),
             _ => Err(self.error(errors::ErrorType::UnexpectedToken(
                 self.current_token.clone().unwrap(),
@@ -188,10 +387,116 @@ impl Parser {
         }
     }
 
+    pub fn parse_list(&mut self) -> Result<Expr, Error> {
+        self.next_token();
+        let mut items = Vec::new();
+        while self.current_token != Some(tokens::Token::RBracket) {
+            items.push(self.parse_expr()?);
+            if items.len() == 1 && self.at_comprehension() {
+                let comprehension = self.parse_comprehension()?;
+                self.expect(tokens::Token::RBracket)?;
+                return Ok(Expr::ListComprehension {
+                    element: Box::new(items.remove(0)),
+                    comprehension: Box::new(comprehension),
+                });
+            }
+            self.expect_comma_or(&tokens::Token::RBracket)?;
+        }
+        self.next_token();
+        Ok(Expr::List(items))
+    }
+
+    /// Parse a map literal, like `{"a": 1, "b": 2}`, or a map comprehension
+    pub fn parse_map(&mut self) -> Result<Expr, Error> {
+        self.next_token();
+        let mut entries = Vec::new();
+        while self.current_token != Some(tokens::Token::RBrace) {
+            let key = self.parse_expr()?;
+            self.expect(tokens::Token::Colon)?;
+            let value = self.parse_expr()?;
+            if entries.is_empty() && self.at_comprehension() {
+                let comprehension = self.parse_comprehension()?;
+                self.expect(tokens::Token::RBrace)?;
+                return Ok(Expr::MapComprehension {
+                    key: Box::new(key),
+                    value: Box::new(value),
+                    comprehension: Box::new(comprehension),
+                });
+            }
+            entries.push((key, value));
+            self.expect_comma_or(&tokens::Token::RBrace)?;
+        }
+        self.next_token();
+        Ok(Expr::Map(entries))
+    }
+
+    fn at_comprehension(&self) -> bool {
+        self.current_token == Some(tokens::Token::Identifier("for".into()))
+    }
+
+    /// Parse the `for pattern in iterable if condition` part of a comprehension
+    fn parse_comprehension(&mut self) -> Result<Comprehension, Error> {
+        let (pattern, iterable) = self.parse_for_head()?;
+        let condition = if self.current_token == Some(tokens::Token::Identifier("if".into())) {
+            self.next_token();
+            Some(Box::new(self.parse_expr()?))
+        } else {
+            None
+        };
+        Ok(Comprehension {
+            pattern,
+            iterable: Box::new(iterable),
+            condition,
+        })
+    }
+
+    /// Consume the expected token, or fail
+    pub(crate) fn expect(&mut self, token: tokens::Token) -> Result<(), Error> {
+        if self.current_token != Some(token.clone()) {
+            return Err(self.error(errors::ErrorType::ExpectedToken(token)));
+        }
+        self.next_token();
+        Ok(())
+    }
+
+    /// Parse an expression, or a tuple if it is followed by commas (`1, 2, 3`)
+    pub fn parse_tuple_or_expr(&mut self) -> Result<Expr, Error> {
+        let first = self.parse_expr()?;
+        if self.in_match_arm || self.current_token != Some(tokens::Token::Comma) {
+            return Ok(first);
+        }
+        let mut items = vec![first];
+        while self.current_token == Some(tokens::Token::Comma) {
+            self.next_token();
+            items.push(self.parse_expr()?);
+        }
+        Ok(Expr::Tuple(items))
+    }
+
     pub fn parse_paren(&mut self) -> Result<Expr, Error> {
         let l_par_pos = self.pos;
         self.next_token();
-        let expr = self.parse_expr()?;
+        if self.current_token == Some(tokens::Token::RParen) {
+            self.next_token();
+            return Ok(Expr::Tuple(Vec::new()));
+        }
+        let mut expr = self.parse_expr()?;
+        if self.at_comprehension() {
+            expr = Expr::Generator {
+                element: Box::new(expr),
+                comprehension: Box::new(self.parse_comprehension()?),
+            };
+        } else if self.current_token == Some(tokens::Token::Comma) {
+            let mut items = vec![expr];
+            while self.current_token == Some(tokens::Token::Comma) {
+                self.next_token();
+                if self.current_token == Some(tokens::Token::RParen) {
+                    break;
+                }
+                items.push(self.parse_expr()?);
+            }
+            expr = Expr::Tuple(items);
+        }
         if self.current_token != Some(tokens::Token::RParen) {
             return Err(Error::new(
                 errors::ErrorType::UnClosedParenthesis,
@@ -211,13 +516,7 @@ mod tests {
     use super::*;
 
     fn parse(input: &str) -> Result<Expr, Error> {
-        Parser {
-            input: input.to_string(),
-            pos: 0,
-            current_token: None,
-            next_token: None,
-        }
-        .parse_expr()
+        Parser::new(input).parse_expr()
     }
 
     #[test]
@@ -324,6 +623,47 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_tuples() {
+        assert_eq!(
+            parse("(1, 2)"),
+            Ok(Expr::Tuple(vec![Expr::Number(1.0), Expr::Number(2.0)]))
+        );
+        assert_eq!(parse("(1,)"), Ok(Expr::Tuple(vec![Expr::Number(1.0)])));
+        assert_eq!(parse("()"), Ok(Expr::Tuple(vec![])));
+        assert!(parse("(1, 2").is_err());
+    }
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(
+            parse("0..n + 1"),
+            Ok(Expr::Range {
+                start: Box::new(Expr::Number(0.0)),
+                end: Box::new(Expr::BinaryOp {
+                    op: Token::Plus,
+                    lhs: Box::new(Expr::Identifier("n".into())),
+                    rhs: Box::new(Expr::Number(1.0)),
+                }),
+                inclusive: false,
+            })
+        );
+        assert_eq!(
+            parse("(1..=3).step(2)"),
+            Ok(Expr::Call(
+                Box::new(Expr::Acessor(vec![
+                    Expr::Range {
+                        start: Box::new(Expr::Number(1.0)),
+                        end: Box::new(Expr::Number(3.0)),
+                        inclusive: true,
+                    },
+                    Expr::Identifier("step".into()),
+                ])),
+                vec![Expr::Number(2.0)]
+            ))
+        );
+    }
+
     #[test]
     fn test_whitespace_handling() {
         assert_eq!(
@@ -339,4 +679,102 @@ mod tests {
             })
         );
     }
+
+    #[test]
+    fn test_parse_comprehensions() {
+        let ident = |name: &str| Expr::Identifier(name.into());
+        let comprehension = |pattern, condition: Option<Expr>| {
+            Box::new(Comprehension {
+                pattern,
+                iterable: Box::new(ident("xs")),
+                condition: condition.map(Box::new),
+            })
+        };
+        let double = Expr::BinaryOp {
+            op: Token::Star,
+            lhs: Box::new(ident("x")),
+            rhs: Box::new(Expr::Number(2.0)),
+        };
+        let positive = Expr::BinaryOp {
+            op: Token::GreaterThan,
+            lhs: Box::new(ident("x")),
+            rhs: Box::new(Expr::Number(0.0)),
+        };
+        assert_eq!(
+            parse("[x * 2 for x in xs if x > 0]"),
+            Ok(Expr::ListComprehension {
+                element: Box::new(double.clone()),
+                comprehension: comprehension(Pattern::Binding("x".into()), Some(positive)),
+            })
+        );
+        assert_eq!(
+            parse("{k: v for k, v in xs}"),
+            Ok(Expr::MapComprehension {
+                key: Box::new(ident("k")),
+                value: Box::new(ident("v")),
+                comprehension: comprehension(
+                    Pattern::Tuple(vec![
+                        Pattern::Binding("k".into()),
+                        Pattern::Binding("v".into())
+                    ]),
+                    None
+                ),
+            })
+        );
+        let generator = Expr::Generator {
+            element: Box::new(double),
+            comprehension: comprehension(Pattern::Binding("x".into()), None),
+        };
+        assert_eq!(parse("(x * 2 for x in xs)"), Ok(generator.clone()));
+        assert_eq!(
+            parse("sum(x * 2 for x in xs)"),
+            Ok(Expr::Call(Box::new(ident("sum")), vec![generator]))
+        );
+        assert!(parse("[x for x of xs]").is_err());
+    }
+
+    #[test]
+    fn test_parse_map() {
+        assert_eq!(
+            parse(r#"{"a": 1, "b": 2}"#),
+            Ok(Expr::Map(vec![
+                (Expr::String("a".into()), Expr::Number(1.0)),
+                (Expr::String("b".into()), Expr::Number(2.0)),
+            ]))
+        );
+        assert_eq!(parse("{}"), Ok(Expr::Map(vec![])));
+        assert!(parse(r#"{"a" 1}"#).is_err());
+    }
+
+    #[test]
+    fn test_parse_pipeline() {
+        let ident = |name: &str| Expr::Identifier(name.into());
+        let call = |name: &str, args| Expr::Call(Box::new(ident(name)), args);
+        let expected = call(
+            "save",
+            vec![call("filter", vec![ident("data"), ident("p")])],
+        );
+        assert_eq!(parse("data |> filter(p) |> save"), Ok(expected.clone()));
+        assert_eq!(parse("data\n  |> filter(p)\n  |> save()"), Ok(expected));
+        // Comparisons are done before piping
+        assert_eq!(
+            parse("a == b |> show()"),
+            Ok(call(
+                "show",
+                vec![Expr::BinaryOp {
+                    op: Token::Equal,
+                    lhs: Box::new(ident("a")),
+                    rhs: Box::new(ident("b")),
+                }]
+            ))
+        );
+        assert_eq!(
+            parse("xs |> Std.print"),
+            Ok(Expr::Call(
+                Box::new(Expr::Acessor(vec![ident("Std"), ident("print")])),
+                vec![ident("xs")]
+            ))
+        );
+        assert!(parse("x |> 3").is_err());
+    }
 }
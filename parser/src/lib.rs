@@ -1,8 +1,15 @@
-use errors::Error;
+use std::collections::HashMap;
+
+use errors::{Error, Warning};
+use macros::Macro;
 use statement::Statement;
 
+pub mod check;
+pub mod class;
 pub mod errors;
 pub mod expr;
+pub mod macros;
+pub mod pattern;
 pub mod statement;
 pub mod tokens;
 
@@ -10,33 +17,96 @@ pub mod tokens;
 /// In this, we use a algorithm called recursive descent parsing, which is a top-down parsing technique that constructs a parse tree from the top and the input is read from left to right.
 pub struct Parser {
     pub input: String,
+    /// The position after the current token
     pub pos: usize,
+    /// The position where the current token starts
+    pub token_pos: usize,
+    /// Whether a line ends between the previous token and the current one
+    pub newline_before: bool,
     pub current_token: Option<tokens::Token>,
     pub next_token: Option<tokens::Token>,
+    /// The block scopes currently open, innermost last.
+    /// Each scope maps the names declared with `let`/`const` to whether they are constant,
+    /// so reassigning a constant can be reported before the program runs.
+    pub scopes: Vec<HashMap<String, bool>>,
+    /// The warnings found so far, which don't prevent the program from running
+    pub warnings: Vec<Warning>,
+    /// Set while parsing the body of a match arm written without braces,
+    /// where commas separate the arms instead of building tuples.
+    pub in_match_arm: bool,
+    /// The variants of the enums declared so far, used to check if a match handles all of them
+    pub enums: HashMap<String, Vec<String>>,
+    /// Leave the `assert` statements out of the parsed program
+    pub optimize: bool,
+    /// The macros declared so far, expanded where they are called
+    pub macros: HashMap<String, Macro>,
+    /// The number of names renamed by macro expansions, used to make the new names unique
+    pub macro_expansions: usize,
+    /// Wrap the parsed statements with their position in the input, for the passes reporting errors after parsing
+    pub positions: bool,
+}
+
+/// A saved position of the parser, to come back to it when an attempt to parse something fails
+#[derive(Clone)]
+struct Checkpoint {
+    pos: usize,
+    token_pos: usize,
+    newline_before: bool,
+    current_token: Option<tokens::Token>,
+    next_token: Option<tokens::Token>,
 }
 
 impl Parser {
-    /// Parse a program from a string
-    /// A program is a sequence of statements, which are returned as a Block statement for convenience.
-    pub fn parse(input: &str) -> Result<Statement, Error> {
+    /// Create a parser positioned on the first token of the input, with the top-level scope open.
+    pub fn new(input: &str) -> Self {
         let mut parser = Parser {
             input: input.to_string(),
             pos: 0,
+            token_pos: 0,
+            newline_before: false,
             current_token: None,
             next_token: None,
+            scopes: vec![HashMap::new()],
+            warnings: Vec::new(),
+            in_match_arm: false,
+            enums: HashMap::new(),
+            optimize: false,
+            macros: HashMap::new(),
+            macro_expansions: 0,
+            positions: false,
         };
         parser.next_token();
+        parser
+    }
+
+    /// Parse a program from a string
+    /// A program is a sequence of statements, which are returned as a Block statement for convenience.
+    pub fn parse(input: &str) -> Result<Statement, Error> {
+        Self::parse_with_warnings(input).map(|(program, _)| program)
+    }
+
+    /// Parse a program like `Parser::parse`, also returning the warnings found in it
+    pub fn parse_with_warnings(input: &str) -> Result<(Statement, Vec<Warning>), Error> {
+        Parser::new(input).parse_program()
+    }
+
+    /// Parse the whole input as a program, returning it with the warnings found in it
+    pub fn parse_program(mut self) -> Result<(Statement, Vec<Warning>), Error> {
         let mut statements = vec![];
-        while parser.current_token.is_some() {
-            statements.push(parser.parse_statement()?);
+        while self.current_token.is_some() {
+            let statement = self.parse_statement()?;
+            self.push_statement(&mut statements, statement);
         }
-        Ok(Statement::Block(statements))
+        Ok((Statement::Block(statements), self.warnings))
     }
 
     /// Tokenize the next token in the input and store it in the current_token field
     /// This basically turn the next part of the input into a token and store it in the current_token field. <br/>
     /// For example, if the input is `let x = 1+1*(3-5)`, the first token would be `let`, the second would be `x`, etc...
     pub fn next_token(&mut self) {
+        let input = &self.input[self.pos..];
+        self.token_pos = self.pos + input.len() - tokens::Token::skip_blank(input).len();
+        self.newline_before = self.input[self.pos..self.token_pos].contains('\n');
         let result = tokens::Token::tokenize_first(&self.input[self.pos..]);
         if let Some((token, rest)) = result {
             self.pos += self.input[self.pos..].len() - rest.len();
@@ -56,6 +126,24 @@ impl Parser {
         }
     }
 
+    fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            pos: self.pos,
+            token_pos: self.token_pos,
+            newline_before: self.newline_before,
+            current_token: self.current_token.clone(),
+            next_token: self.next_token.clone(),
+        }
+    }
+
+    fn restore(&mut self, checkpoint: Checkpoint) {
+        self.pos = checkpoint.pos;
+        self.token_pos = checkpoint.token_pos;
+        self.newline_before = checkpoint.newline_before;
+        self.current_token = checkpoint.current_token;
+        self.next_token = checkpoint.next_token;
+    }
+
     /// Shortcut to generate an error
     fn error(&self, error_type: errors::ErrorType) -> Error {
         Error::new(error_type, self.pos, self.input.clone())
@@ -0,0 +1,551 @@
+use std::collections::HashMap;
+
+use crate::{
+    class::Method,
+    errors::{self, Error},
+    expr::{Comprehension, Expr},
+    pattern::{MatchArm, Pattern},
+    statement::Statement,
+    tokens, Parser,
+};
+
+/// A macro declared with `macro name(params) { body }`.
+/// Calling it with `name!(args)` replaces the call by the body, with the parameters replaced by the arguments.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Macro {
+    pub params: Vec<String>,
+    pub body: Vec<Statement>,
+}
+
+impl Parser {
+    /// Parse a macro declaration and register the macro, the current token being `macro`.
+    /// The macro can be called by the code following it.
+    pub fn parse_macro(&mut self) -> Result<Statement, Error> {
+        self.next_token();
+        let name = self.expect_identifier()?;
+        if self.current_token != Some(tokens::Token::LParen) {
+            return Err(self.error(errors::ErrorType::ExpectedToken(tokens::Token::LParen)));
+        }
+        self.next_token();
+        let mut params = Vec::new();
+        while self.current_token != Some(tokens::Token::RParen) {
+            params.push(self.expect_identifier()?);
+            self.expect_comma_or(&tokens::Token::RParen)?;
+        }
+        self.next_token();
+        let body = self.parse_block()?;
+
+        let definition = Macro { params, body };
+        self.macros.insert(name.clone(), definition.clone());
+        Ok(Statement::Macro {
+            name,
+            params: definition.params,
+            body: definition.body,
+        })
+    }
+
+    /// Parse a macro call, `name!(args)`, and return the statements it expands to
+    pub fn parse_macro_call(&mut self) -> Result<Vec<Statement>, Error> {
+        let call_pos = self.pos;
+        let name = self.expect_identifier()?;
+        self.next_token();
+        if self.current_token != Some(tokens::Token::LParen) {
+            return Err(self.error(errors::ErrorType::ExpectedToken(tokens::Token::LParen)));
+        }
+        self.next_token();
+        let mut args = Vec::new();
+        while self.current_token != Some(tokens::Token::RParen) {
+            args.push(self.parse_expr()?);
+            self.expect_comma_or(&tokens::Token::RParen)?;
+        }
+        self.next_token();
+
+        let error = |error_type| Error::new(error_type, call_pos, self.input.clone());
+        let definition = self
+            .macros
+            .get(&name)
+            .ok_or_else(|| error(errors::ErrorType::UnknownMacro(name.clone())))?;
+        if args.len() != definition.params.len() {
+            return Err(error(errors::ErrorType::MacroArity {
+                name,
+                expected: definition.params.len(),
+                got: args.len(),
+            }));
+        }
+
+        let mut expansion = Expansion {
+            args: definition.params.iter().cloned().zip(args).collect(),
+            renames: HashMap::new(),
+            bound: Some(Vec::new()),
+        };
+        // The names bound by the macro get a fresh name, which can't be written in a program,
+        // so they never clash with the names of the code calling it
+        expansion
+            .statements(&definition.body)
+            .map_err(|name| error(errors::ErrorType::MacroArgumentNotAName(name)))?;
+        for name in expansion.bound.take().unwrap_or_default() {
+            self.macro_expansions += 1;
+            let fresh = format!("{}#{}", name, self.macro_expansions);
+            expansion.renames.insert(name, fresh);
+        }
+        expansion
+            .statements(&definition.body)
+            .map_err(|name| error(errors::ErrorType::MacroArgumentNotAName(name)))
+    }
+
+    /// Parse a macro call used as a value. It evaluates to the value of the last expression of the expansion.
+    pub fn parse_macro_call_expr(&mut self) -> Result<Expr, Error> {
+        let statements = self.parse_macro_call()?;
+        match statements.as_slice() {
+            [statement] => match statement.unlocated() {
+                Statement::Expr(expr) => Ok(expr.clone()),
+                _ => Ok(Expr::Block(statements)),
+            },
+            _ => Ok(Expr::Block(statements)),
+        }
+    }
+}
+
+/// The rewriting of a macro body for one call.
+/// It runs twice: first to find the names the body binds (while `bound` is set), then to build the expansion.
+struct Expansion {
+    args: HashMap<String, Expr>,
+    renames: HashMap<String, String>,
+    bound: Option<Vec<String>>,
+}
+
+/// A rewriting fails with the name of a parameter used where a name is expected, but given something else
+type Rewrite<T> = Result<T, String>;
+
+impl Expansion {
+    /// The name to use where the body binds `name`
+    fn bind(&mut self, name: &str) -> Rewrite<String> {
+        if let Some(arg) = self.args.get(name) {
+            return match arg {
+                Expr::Identifier(arg) => Ok(arg.clone()),
+                _ => Err(name.to_string()),
+            };
+        }
+        if let Some(bound) = &mut self.bound {
+            if !bound.iter().any(|bound| bound == name) {
+                bound.push(name.to_string());
+            }
+        }
+        Ok(self.renames.get(name).cloned().unwrap_or(name.to_string()))
+    }
+
+    /// The name to use where the body refers to a class or an interface by its name, like in `x is Comparable`
+    fn name(&self, name: &str) -> String {
+        match self.args.get(name) {
+            Some(Expr::Identifier(arg)) => arg.clone(),
+            _ => self.renames.get(name).cloned().unwrap_or(name.to_string()),
+        }
+    }
+
+    /// The expression to use where the body reads `name`
+    fn reference(&self, name: &str) -> Expr {
+        match self.args.get(name) {
+            Some(arg) => arg.clone(),
+            None => Expr::Identifier(self.renames.get(name).cloned().unwrap_or(name.to_string())),
+        }
+    }
+
+    fn statements(&mut self, statements: &[Statement]) -> Rewrite<Vec<Statement>> {
+        statements.iter().map(|s| self.statement(s)).collect()
+    }
+
+    fn statement(&mut self, statement: &Statement) -> Rewrite<Statement> {
+        Ok(match statement {
+            Statement::Return(expr) => Statement::Return(self.expr(expr)?),
+            Statement::Expr(expr) => Statement::Expr(self.expr(expr)?),
+            Statement::Defer(expr) => Statement::Defer(self.expr(expr)?),
+            Statement::Block(body) => Statement::Block(self.statements(body)?),
+            Statement::Fn {
+                name,
+                params,
+                return_type,
+                body,
+            } => Statement::Fn {
+                name: self.bind(name)?,
+                params: self.patterns(params)?,
+                return_type: return_type.clone(),
+                body: self.statements(body)?,
+            },
+            Statement::Assign(name, value) => {
+                Statement::Assign(self.bind(name)?, self.expr(value)?)
+            }
+            Statement::Destructure(pattern, value) => {
+                Statement::Destructure(self.pattern(pattern)?, self.expr(value)?)
+            }
+            Statement::Let {
+                pattern,
+                value,
+                constant,
+            } => Statement::Let {
+                pattern: self.pattern(pattern)?,
+                value: self.expr(value)?,
+                constant: *constant,
+            },
+            Statement::If {
+                condition,
+                body,
+                else_body,
+            } => Statement::If {
+                condition: self.expr(condition)?,
+                body: self.statements(body)?,
+                else_body: self.statements(else_body)?,
+            },
+            Statement::Match { subject, arms } => Statement::Match {
+                subject: self.expr(subject)?,
+                arms: self.arms(arms)?,
+            },
+            Statement::For {
+                pattern,
+                iterable,
+                body,
+            } => Statement::For {
+                pattern: self.pattern(pattern)?,
+                iterable: self.expr(iterable)?,
+                body: self.statements(body)?,
+            },
+            Statement::Assert {
+                condition,
+                message,
+                line,
+                source,
+            } => Statement::Assert {
+                condition: self.expr(condition)?,
+                message: message.as_ref().map(|m| self.expr(m)).transpose()?,
+                line: *line,
+                source: source.clone(),
+            },
+            Statement::With {
+                resource,
+                binding,
+                body,
+            } => Statement::With {
+                resource: self.expr(resource)?,
+                binding: binding.as_ref().map(|b| self.pattern(b)).transpose()?,
+                body: self.statements(body)?,
+            },
+            Statement::Enum { name, variants } => Statement::Enum {
+                name: self.bind(name)?,
+                variants: variants.clone(),
+            },
+            Statement::Class {
+                name,
+                fields,
+                interfaces,
+                methods,
+                statics,
+            } => Statement::Class {
+                name: self.bind(name)?,
+                fields: fields.clone(),
+                interfaces: interfaces.iter().map(|i| self.name(i)).collect(),
+                methods: methods
+                    .iter()
+                    .map(|method| {
+                        Ok(Method {
+                            kind: method.kind,
+                            name: method.name.clone(),
+                            params: self.patterns(&method.params)?,
+                            return_type: method.return_type.clone(),
+                            body: self.statements(&method.body)?,
+                        })
+                    })
+                    .collect::<Rewrite<_>>()?,
+                statics: statics
+                    .iter()
+                    .map(|(name, value)| Ok((name.clone(), self.expr(value)?)))
+                    .collect::<Rewrite<_>>()?,
+            },
+            Statement::SetField {
+                object,
+                field,
+                value,
+            } => Statement::SetField {
+                object: self.expr(object)?,
+                field: field.clone(),
+                value: self.expr(value)?,
+            },
+            Statement::Interface { name, methods } => Statement::Interface {
+                name: self.bind(name)?,
+                methods: methods.clone(),
+            },
+            Statement::Macro { .. } => statement.clone(),
+            Statement::Located { pos, statement } => Statement::Located {
+                pos: *pos,
+                statement: Box::new(self.statement(statement)?),
+            },
+        })
+    }
+
+    fn exprs(&mut self, exprs: &[Expr]) -> Rewrite<Vec<Expr>> {
+        exprs.iter().map(|e| self.expr(e)).collect()
+    }
+
+    fn boxed(&mut self, expr: &Expr) -> Rewrite<Box<Expr>> {
+        Ok(Box::new(self.expr(expr)?))
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Rewrite<Expr> {
+        Ok(match expr {
+            Expr::Number(_) | Expr::Boolean(_) | Expr::String(_) => expr.clone(),
+            Expr::Identifier(name) => self.reference(name),
+            Expr::List(items) => Expr::List(self.exprs(items)?),
+            Expr::Tuple(items) => Expr::Tuple(self.exprs(items)?),
+            Expr::Map(entries) => Expr::Map(
+                entries
+                    .iter()
+                    .map(|(key, value)| Ok((self.expr(key)?, self.expr(value)?)))
+                    .collect::<Rewrite<_>>()?,
+            ),
+            Expr::Acessor(items) => {
+                let mut rewritten = vec![self.expr(&items[0])?];
+                for item in &items[1..] {
+                    rewritten.push(self.member(item)?);
+                }
+                Expr::Acessor(rewritten)
+            }
+            Expr::Call(function, args) => Expr::Call(self.boxed(function)?, self.exprs(args)?),
+            Expr::BinaryOp { op, lhs, rhs } => Expr::BinaryOp {
+                op: op.clone(),
+                lhs: self.boxed(lhs)?,
+                rhs: self.boxed(rhs)?,
+            },
+            Expr::Range {
+                start,
+                end,
+                inclusive,
+            } => Expr::Range {
+                start: self.boxed(start)?,
+                end: self.boxed(end)?,
+                inclusive: *inclusive,
+            },
+            Expr::Match { subject, arms } => Expr::Match {
+                subject: self.boxed(subject)?,
+                arms: self.arms(arms)?,
+            },
+            Expr::If {
+                condition,
+                body,
+                else_body,
+            } => Expr::If {
+                condition: self.boxed(condition)?,
+                body: self.statements(body)?,
+                else_body: self.statements(else_body)?,
+            },
+            Expr::ListComprehension {
+                element,
+                comprehension,
+            } => Expr::ListComprehension {
+                comprehension: self.comprehension(comprehension)?,
+                element: self.boxed(element)?,
+            },
+            Expr::MapComprehension {
+                key,
+                value,
+                comprehension,
+            } => Expr::MapComprehension {
+                comprehension: self.comprehension(comprehension)?,
+                key: self.boxed(key)?,
+                value: self.boxed(value)?,
+            },
+            Expr::Generator {
+                element,
+                comprehension,
+            } => Expr::Generator {
+                comprehension: self.comprehension(comprehension)?,
+                element: self.boxed(element)?,
+            },
+            Expr::Block(body) => Expr::Block(self.statements(body)?),
+            Expr::Is { value, class } => Expr::Is {
+                value: self.boxed(value)?,
+                class: self.name(class),
+            },
+            Expr::Located { pos, expr } => Expr::Located {
+                pos: *pos,
+                expr: self.boxed(expr)?,
+            },
+        })
+    }
+
+    /// Rewrite an expression following a `.`, where names are fields or methods and not variables
+    fn member(&mut self, expr: &Expr) -> Rewrite<Expr> {
+        Ok(match expr {
+            Expr::Identifier(_) => expr.clone(),
+            Expr::Call(method, args) => {
+                Expr::Call(Box::new(self.member(method)?), self.exprs(args)?)
+            }
+            Expr::Located { pos, expr } => Expr::Located {
+                pos: *pos,
+                expr: Box::new(self.member(expr)?),
+            },
+            Expr::Acessor(items) => Expr::Acessor(
+                items
+                    .iter()
+                    .map(|item| self.member(item))
+                    .collect::<Rewrite<_>>()?,
+            ),
+            _ => self.expr(expr)?,
+        })
+    }
+
+    fn comprehension(&mut self, comprehension: &Comprehension) -> Rewrite<Box<Comprehension>> {
+        Ok(Box::new(Comprehension {
+            pattern: self.pattern(&comprehension.pattern)?,
+            iterable: self.boxed(&comprehension.iterable)?,
+            condition: comprehension
+                .condition
+                .as_ref()
+                .map(|c| self.boxed(c))
+                .transpose()?,
+        }))
+    }
+
+    fn arms(&mut self, arms: &[MatchArm]) -> Rewrite<Vec<MatchArm>> {
+        arms.iter()
+            .map(|arm| {
+                Ok(MatchArm {
+                    pattern: self.pattern(&arm.pattern)?,
+                    guard: arm.guard.as_ref().map(|g| self.expr(g)).transpose()?,
+                    body: self.statements(&arm.body)?,
+                })
+            })
+            .collect()
+    }
+
+    fn patterns(&mut self, patterns: &[Pattern]) -> Rewrite<Vec<Pattern>> {
+        patterns.iter().map(|p| self.pattern(p)).collect()
+    }
+
+    fn pattern(&mut self, pattern: &Pattern) -> Rewrite<Pattern> {
+        Ok(match pattern {
+            Pattern::Wildcard | Pattern::Rest(None) => pattern.clone(),
+            Pattern::Literal(expr) => Pattern::Literal(self.expr(expr)?),
+            Pattern::Binding(name) => Pattern::Binding(self.bind(name)?),
+            Pattern::Rest(Some(name)) => Pattern::Rest(Some(self.bind(name)?)),
+            Pattern::Alternatives(patterns) => Pattern::Alternatives(self.patterns(patterns)?),
+            Pattern::List(patterns) => Pattern::List(self.patterns(patterns)?),
+            Pattern::Tuple(patterns) => Pattern::Tuple(self.patterns(patterns)?),
+            Pattern::Class { name, fields } => Pattern::Class {
+                name: name.clone(),
+                fields: self.patterns(fields)?,
+            },
+            Pattern::Typed {
+                pattern,
+                annotation,
+            } => Pattern::Typed {
+                pattern: Box::new(self.pattern(pattern)?),
+                annotation: annotation.clone(),
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::ErrorType;
+
+    fn ident(name: &str) -> Expr {
+        Expr::Identifier(name.into())
+    }
+
+    #[test]
+    fn test_expand_expression_macro() {
+        assert_eq!(
+            Parser::parse("macro square(x) { x * x } y = square!(a + 1)"),
+            Ok(Statement::Block(vec![Statement::Assign(
+                "y".into(),
+                Expr::BinaryOp {
+                    op: tokens::Token::Star,
+                    lhs: Box::new(Expr::BinaryOp {
+                        op: tokens::Token::Plus,
+                        lhs: Box::new(ident("a")),
+                        rhs: Box::new(Expr::Number(1.0)),
+                    }),
+                    rhs: Box::new(Expr::BinaryOp {
+                        op: tokens::Token::Plus,
+                        lhs: Box::new(ident("a")),
+                        rhs: Box::new(Expr::Number(1.0)),
+                    }),
+                }
+            )]))
+        );
+    }
+
+    #[test]
+    fn test_expansion_is_hygienic() {
+        let program = Parser::parse(
+            "macro swap(a, b) { tmp = a  a = b  b = tmp }
+            swap!(tmp, x)
+            swap!(x, tmp)",
+        );
+        let swap = |a: &str, b: &str, tmp: &str| {
+            Statement::Block(vec![
+                Statement::Assign(tmp.into(), ident(a)),
+                Statement::Assign(a.into(), ident(b)),
+                Statement::Assign(b.into(), ident(tmp)),
+            ])
+        };
+        // The macro's `tmp` gets a new name at each call, so it doesn't touch the caller's `tmp`
+        assert_eq!(
+            program,
+            Ok(Statement::Block(vec![
+                swap("tmp", "x", "tmp#1"),
+                swap("x", "tmp", "tmp#2"),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_macro_fields_are_not_renamed() {
+        assert_eq!(
+            Parser::parse(
+                "macro len_plus(xs, n) { total = xs.len() + n  total } y = len_plus!(items, 1)"
+            ),
+            Ok(Statement::Block(vec![Statement::Assign(
+                "y".into(),
+                Expr::Block(vec![
+                    Statement::Assign(
+                        "total#1".into(),
+                        Expr::BinaryOp {
+                            op: tokens::Token::Plus,
+                            lhs: Box::new(Expr::Call(
+                                Box::new(Expr::Acessor(vec![ident("items"), ident("len")])),
+                                vec![]
+                            )),
+                            rhs: Box::new(Expr::Number(1.0)),
+                        }
+                    ),
+                    Statement::Expr(ident("total#1")),
+                ])
+            )]))
+        );
+    }
+
+    #[test]
+    fn test_macro_errors() {
+        let error = |source: &str| match Parser::parse(source) {
+            Err(error) => error.error_type().clone(),
+            Ok(program) => panic!("Expected an error, got {:?}", program),
+        };
+        assert_eq!(
+            error("square!(1)"),
+            ErrorType::UnknownMacro("square".into())
+        );
+        assert_eq!(
+            error("macro square(x) { x * x } square!(1, 2)"),
+            ErrorType::MacroArity {
+                name: "square".into(),
+                expected: 1,
+                got: 2
+            }
+        );
+        assert_eq!(
+            error("macro define(name) { fn name() { } } define!(1)"),
+            ErrorType::MacroArgumentNotAName("name".into())
+        );
+    }
+}
@@ -0,0 +1,482 @@
+use std::collections::HashMap;
+
+use crate::{
+    errors::{self, Error, Warning},
+    expr::Expr,
+    statement::Statement,
+    tokens, Parser,
+};
+
+/// A pattern is the left part of a match arm, describing the shape of the values the arm accepts.
+/// For example, in `match point { Point(0, y) => ... }`, `Point(0, y)` accepts the points on the y axis
+/// and binds their second field to `y`.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Pattern {
+    /// `_`, matches any value
+    Wildcard,
+    /// A number, string or boolean literal, matches the values equal to it
+    Literal(Expr),
+    /// A name, matches any value and binds it to the name in the arm
+    Binding(String),
+    /// `1 | 2 | 3`, matches if any of the alternatives matches
+    Alternatives(Vec<Pattern>),
+    /// `Point(x, y)`, matches the instances of a class and destructures their fields.
+    /// The name can be qualified by an enum, like `Status.Done(result)`, and the fields can be left out
+    /// for enum variants without payload, like `Status.Pending`.
+    Class { name: String, fields: Vec<Pattern> },
+    /// `[first, *rest]`, matches the lists whose items match the inner patterns
+    List(Vec<Pattern>),
+    /// `(a, b)`, matches the tuples whose items match the inner patterns
+    Tuple(Vec<Pattern>),
+    /// `*rest` inside a list pattern, matches all the remaining items (`*_` ignores them)
+    Rest(Option<String>),
+    /// A pattern with a type annotation, like `a: Number` in `fn add(a: Number, b: Number)`.
+    /// The annotation is only used by the type checker.
+    Typed {
+        pattern: Box<Pattern>,
+        annotation: String,
+    },
+}
+
+impl Pattern {
+    /// The names bound by the pattern when it matches
+    pub fn bindings(&self) -> Vec<String> {
+        match self {
+            Pattern::Binding(name) | Pattern::Rest(Some(name)) => vec![name.clone()],
+            Pattern::Alternatives(patterns)
+            | Pattern::List(patterns)
+            | Pattern::Tuple(patterns)
+            | Pattern::Class {
+                fields: patterns, ..
+            } => patterns.iter().flat_map(Pattern::bindings).collect(),
+            Pattern::Typed { pattern, .. } => pattern.bindings(),
+            Pattern::Wildcard | Pattern::Literal(_) | Pattern::Rest(None) => vec![],
+        }
+    }
+
+    /// Whether the pattern matches every possible value
+    pub fn is_irrefutable(&self) -> bool {
+        match self {
+            Pattern::Wildcard | Pattern::Binding(_) => true,
+            Pattern::Alternatives(patterns) => patterns.iter().any(Pattern::is_irrefutable),
+            Pattern::Typed { pattern, .. } => pattern.is_irrefutable(),
+            _ => false,
+        }
+    }
+}
+
+/// One arm of a match: `pattern if guard => body`
+#[derive(Debug, PartialEq, Clone)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub guard: Option<Expr>,
+    pub body: Vec<Statement>,
+}
+
+impl Parser {
+    /// Parse a `match subject { arms }` construct, the current token being `match`.
+    /// It returns the subject and the arms, so it can be used for both the statement and the expression.
+    pub fn parse_match(&mut self) -> Result<(Expr, Vec<MatchArm>), Error> {
+        let match_pos = self.pos;
+        self.next_token();
+        let subject = self.parse_expr()?;
+
+        if self.current_token != Some(tokens::Token::LBrace) {
+            return Err(self.error(errors::ErrorType::ExpectedToken(tokens::Token::LBrace)));
+        }
+        self.next_token();
+
+        let mut arms = Vec::new();
+        while self.current_token != Some(tokens::Token::RBrace) {
+            if self.current_token.is_none() {
+                return Err(self.error(errors::ErrorType::UnexpectedEndOfFile));
+            }
+            arms.push(self.parse_match_arm()?);
+            if self.current_token == Some(tokens::Token::Comma) {
+                self.next_token();
+            }
+        }
+        self.next_token();
+
+        if !self.is_exhaustive(&arms) {
+            self.warnings.push(Warning::new(
+                errors::WarningType::NonExhaustiveMatch,
+                match_pos,
+                self.input.clone(),
+            ));
+        }
+
+        Ok((subject, arms))
+    }
+
+    fn parse_match_arm(&mut self) -> Result<MatchArm, Error> {
+        let pattern = self.parse_pattern()?;
+
+        // The names bound by the pattern are visible in the guard and the body
+        self.scopes.push(
+            pattern
+                .bindings()
+                .into_iter()
+                .map(|name| (name, false))
+                .collect::<HashMap<_, _>>(),
+        );
+        let arm = self.parse_match_arm_rest(pattern);
+        self.scopes.pop();
+        arm
+    }
+
+    fn parse_match_arm_rest(&mut self, pattern: Pattern) -> Result<MatchArm, Error> {
+        let guard = if self.current_token == Some(tokens::Token::Identifier("if".into())) {
+            self.next_token();
+            Some(self.parse_expr()?)
+        } else {
+            None
+        };
+
+        if self.current_token != Some(tokens::Token::FatArrow) {
+            return Err(self.error(errors::ErrorType::ExpectedToken(tokens::Token::FatArrow)));
+        }
+        self.next_token();
+
+        let body = if self.current_token == Some(tokens::Token::LBrace) {
+            self.parse_block()?
+        } else {
+            let in_match_arm = std::mem::replace(&mut self.in_match_arm, true);
+            let statement = self.parse_statement();
+            self.in_match_arm = in_match_arm;
+            let mut body = Vec::new();
+            self.push_statement(&mut body, statement?);
+            body
+        };
+
+        Ok(MatchArm {
+            pattern,
+            guard,
+            body,
+        })
+    }
+
+    /// Parse a pattern, with its alternatives if there are any
+    pub fn parse_pattern(&mut self) -> Result<Pattern, Error> {
+        let mut pattern = self.parse_single_pattern()?;
+        if self.current_token == Some(tokens::Token::Pipe) {
+            let mut alternatives = vec![pattern];
+            while self.current_token == Some(tokens::Token::Pipe) {
+                self.next_token();
+                alternatives.push(self.parse_single_pattern()?);
+            }
+            pattern = Pattern::Alternatives(alternatives);
+        }
+
+        if self.current_token == Some(tokens::Token::Colon) {
+            self.next_token();
+            pattern = Pattern::Typed {
+                pattern: Box::new(pattern),
+                annotation: self.expect_identifier()?,
+            };
+        }
+        Ok(pattern)
+    }
+
+    fn parse_single_pattern(&mut self) -> Result<Pattern, Error> {
+        match self.current_token.clone() {
+            Some(tokens::Token::Number(n)) => {
+                self.next_token();
+                Ok(Pattern::Literal(Expr::Number(n)))
+            }
+            Some(tokens::Token::Minus) => {
+                self.next_token();
+                match self.current_token {
+                    Some(tokens::Token::Number(n)) => {
+                        self.next_token();
+                        Ok(Pattern::Literal(Expr::Number(-n)))
+                    }
+                    _ => {
+                        Err(self
+                            .error(errors::ErrorType::ExpectedToken(tokens::Token::Number(0.0))))
+                    }
+                }
+            }
+            Some(tokens::Token::String(s)) => {
+                self.next_token();
+                Ok(Pattern::Literal(Expr::String(s)))
+            }
+            Some(tokens::Token::Identifier(id)) => {
+                self.next_token();
+                let mut name = id.clone();
+                while self.current_token == Some(tokens::Token::Point) {
+                    self.next_token();
+                    match self.current_token.clone() {
+                        Some(tokens::Token::Identifier(part)) => {
+                            self.next_token();
+                            name.push('.');
+                            name.push_str(&part);
+                        }
+                        _ => {
+                            return Err(self.error(errors::ErrorType::ExpectedToken(
+                                tokens::Token::Identifier("".into()),
+                            )))
+                        }
+                    }
+                }
+                match id.as_str() {
+                    _ if self.current_token == Some(tokens::Token::LParen) => {
+                        self.next_token();
+                        let fields = self.parse_pattern_list(tokens::Token::RParen)?;
+                        Ok(Pattern::Class { name, fields })
+                    }
+                    _ if name.contains('.') => Ok(Pattern::Class {
+                        name,
+                        fields: vec![],
+                    }),
+                    "_" => Ok(Pattern::Wildcard),
+                    "true" => Ok(Pattern::Literal(Expr::Boolean(true))),
+                    "false" => Ok(Pattern::Literal(Expr::Boolean(false))),
+                    _ => Ok(Pattern::Binding(id)),
+                }
+            }
+            Some(tokens::Token::LParen) => {
+                self.next_token();
+                let (mut items, has_comma) =
+                    self.parse_sequence_pattern_items(tokens::Token::RParen)?;
+                // Without a comma, `(p)` is just a parenthesized pattern
+                if items.len() == 1 && !has_comma && !matches!(items[0], Pattern::Rest(_)) {
+                    return Ok(items.remove(0));
+                }
+                Ok(Pattern::Tuple(items))
+            }
+            Some(tokens::Token::LBracket) => {
+                self.next_token();
+                let (items, _) = self.parse_sequence_pattern_items(tokens::Token::RBracket)?;
+                Ok(Pattern::List(items))
+            }
+            None => Err(self.error(errors::ErrorType::UnexpectedEndOfFile)),
+            Some(token) => Err(self.error(errors::ErrorType::UnexpectedToken(token))),
+        }
+    }
+
+    /// Parse comma separated patterns until the closing token, which is consumed
+    pub(crate) fn parse_pattern_list(
+        &mut self,
+        closing: tokens::Token,
+    ) -> Result<Vec<Pattern>, Error> {
+        let mut patterns = Vec::new();
+        while self.current_token.as_ref() != Some(&closing) {
+            patterns.push(self.parse_pattern()?);
+            self.expect_comma_or(&closing)?;
+        }
+        self.next_token();
+        Ok(patterns)
+    }
+
+    /// Parse the items of a list or tuple pattern until the closing token, which is consumed.
+    /// One of them can be a `*rest` pattern. Also returns whether the items contained a comma.
+    fn parse_sequence_pattern_items(
+        &mut self,
+        closing: tokens::Token,
+    ) -> Result<(Vec<Pattern>, bool), Error> {
+        let mut patterns = Vec::new();
+        let mut has_comma = false;
+        let mut has_rest = false;
+        while self.current_token.as_ref() != Some(&closing) {
+            if self.current_token == Some(tokens::Token::Star) {
+                if has_rest {
+                    return Err(self.error(errors::ErrorType::UnexpectedToken(tokens::Token::Star)));
+                }
+                has_rest = true;
+                patterns.push(self.parse_rest_pattern()?);
+            } else {
+                patterns.push(self.parse_pattern()?);
+            }
+            has_comma |= self.current_token == Some(tokens::Token::Comma);
+            self.expect_comma_or(&closing)?;
+        }
+        self.next_token();
+        Ok((patterns, has_comma))
+    }
+
+    /// Skip the comma after an item of a list, or check that the list is closed
+    pub(crate) fn expect_comma_or(&mut self, closing: &tokens::Token) -> Result<(), Error> {
+        match &self.current_token {
+            Some(tokens::Token::Comma) => self.next_token(),
+            Some(token) if token == closing => {}
+            _ => return Err(self.error(errors::ErrorType::ExpectedToken(closing.clone()))),
+        }
+        Ok(())
+    }
+
+    /// Parse a `*rest` pattern, the current token being `*`
+    fn parse_rest_pattern(&mut self) -> Result<Pattern, Error> {
+        self.next_token();
+        match self.current_token.clone() {
+            Some(tokens::Token::Identifier(name)) => {
+                self.next_token();
+                Ok(Pattern::Rest((name != "_").then_some(name)))
+            }
+            _ => Err(
+                self.error(errors::ErrorType::ExpectedToken(tokens::Token::Identifier(
+                    "".into(),
+                ))),
+            ),
+        }
+    }
+
+    /// A match is considered exhaustive if it has an arm without guard accepting every value,
+    /// if it handles both `true` and `false`, or if it handles every variant of an enum declared before it.
+    fn is_exhaustive(&self, arms: &[MatchArm]) -> bool {
+        let unguarded = arms.iter().filter(|arm| arm.guard.is_none());
+        let mut patterns = Vec::new();
+        for arm in unguarded {
+            if arm.pattern.is_irrefutable() {
+                return true;
+            }
+            match &arm.pattern {
+                Pattern::Alternatives(alternatives) => patterns.extend(alternatives.iter()),
+                pattern => patterns.push(pattern),
+            }
+        }
+        if patterns.contains(&&Pattern::Literal(Expr::Boolean(true)))
+            && patterns.contains(&&Pattern::Literal(Expr::Boolean(false)))
+        {
+            return true;
+        }
+
+        // The enum variants handled whatever their payload is
+        let variants: Vec<&str> = patterns
+            .iter()
+            .filter_map(|pattern| match pattern {
+                Pattern::Class { name, fields } if fields.iter().all(Pattern::is_irrefutable) => {
+                    Some(name.as_str())
+                }
+                _ => None,
+            })
+            .collect();
+        self.enums.iter().any(|(enum_name, enum_variants)| {
+            enum_variants.iter().all(|variant| {
+                variants.iter().any(|handled| {
+                    *handled == variant || *handled == format!("{}.{}", enum_name, variant)
+                })
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> Result<Pattern, Error> {
+        Parser::new(input).parse_pattern()
+    }
+
+    #[test]
+    fn test_parse_literal_patterns() {
+        assert_eq!(parse("1"), Ok(Pattern::Literal(Expr::Number(1.0))));
+        assert_eq!(parse("-1"), Ok(Pattern::Literal(Expr::Number(-1.0))));
+        assert_eq!(
+            parse("\"x\""),
+            Ok(Pattern::Literal(Expr::String("x".into())))
+        );
+        assert_eq!(parse("true"), Ok(Pattern::Literal(Expr::Boolean(true))));
+    }
+
+    #[test]
+    fn test_parse_alternatives() {
+        assert_eq!(
+            parse("1 | 2 | x"),
+            Ok(Pattern::Alternatives(vec![
+                Pattern::Literal(Expr::Number(1.0)),
+                Pattern::Literal(Expr::Number(2.0)),
+                Pattern::Binding("x".into()),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_parse_class_and_list_patterns() {
+        assert_eq!(
+            parse("Point(0, y)"),
+            Ok(Pattern::Class {
+                name: "Point".into(),
+                fields: vec![
+                    Pattern::Literal(Expr::Number(0.0)),
+                    Pattern::Binding("y".into())
+                ],
+            })
+        );
+        assert_eq!(
+            parse("[first, *rest]"),
+            Ok(Pattern::List(vec![
+                Pattern::Binding("first".into()),
+                Pattern::Rest(Some("rest".into())),
+            ]))
+        );
+        assert_eq!(
+            parse("[_, *_]"),
+            Ok(Pattern::List(vec![Pattern::Wildcard, Pattern::Rest(None)]))
+        );
+        assert!(parse("[*a, *b]").is_err());
+        assert!(parse("*a").is_err());
+        assert!(parse("Point(*a)").is_err());
+        assert!(parse("[a b]").is_err());
+    }
+
+    #[test]
+    fn test_parse_qualified_patterns() {
+        assert_eq!(
+            parse("Status.Pending"),
+            Ok(Pattern::Class {
+                name: "Status.Pending".into(),
+                fields: vec![],
+            })
+        );
+        assert_eq!(
+            parse("Status.Done(r)"),
+            Ok(Pattern::Class {
+                name: "Status.Done".into(),
+                fields: vec![Pattern::Binding("r".into())],
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_tuple_patterns() {
+        assert_eq!(parse("(x)"), Ok(Pattern::Binding("x".into())));
+        assert_eq!(
+            parse("(x,)"),
+            Ok(Pattern::Tuple(vec![Pattern::Binding("x".into())]))
+        );
+        assert_eq!(parse("()"), Ok(Pattern::Tuple(vec![])));
+        assert_eq!(
+            parse("(a, *rest)"),
+            Ok(Pattern::Tuple(vec![
+                Pattern::Binding("a".into()),
+                Pattern::Rest(Some("rest".into())),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_non_exhaustive_warning() {
+        let warnings = |input: &str| Parser::parse_with_warnings(input).unwrap().1.len();
+        assert_eq!(warnings("match x { 1 => 1 }"), 1);
+        assert_eq!(warnings("match x { 1 => 1, _ => 2 }"), 0);
+        assert_eq!(warnings("match x { n if n > 1 => 1 }"), 1);
+        assert_eq!(warnings("match x { 1 | n => 1 }"), 0);
+        assert_eq!(warnings("match x { true => 1, false => 2 }"), 0);
+
+        let status = "enum Status { Pending, Done(result) } ";
+        assert_eq!(
+            warnings(&(status.to_string() + "match x { Status.Pending => 1, Done(_) => 2 }")),
+            0
+        );
+        assert_eq!(
+            warnings(&(status.to_string() + "match x { Status.Pending => 1, Done(1) => 2 }")),
+            1
+        );
+        assert_eq!(
+            warnings(&(status.to_string() + "match x { Done(r) => r }")),
+            1
+        );
+    }
+}
@@ -1,6 +1,10 @@
+use std::collections::HashMap;
+
 use crate::{
+    class::{Method, MethodSignature},
     errors::{self, Error},
     expr::Expr,
+    pattern::{MatchArm, Pattern},
     tokens, Parser,
 };
 
@@ -9,29 +13,146 @@ pub enum Statement {
     Return(Expr),
     Expr(Expr),
     Block(Vec<Statement>),
+    /// Function definition. The parameters are patterns, so `fn f((x, y))` destructures its argument.
+    /// The parameters and the return value can be annotated with their type: `fn add(a: Number, b: Number) -> Number`.
     Fn {
         name: String,
-        params: Vec<String>,
+        params: Vec<Pattern>,
+        return_type: Option<String>,
         body: Vec<Statement>,
     },
     /// Variable assignment
     Assign(String, Expr),
+    /// Destructuring assignment, like `a, b = b, a` or `(x, [y, *rest]) = value`
+    Destructure(Pattern, Expr),
+    /// Block-scoped variable declaration, `let x = ...` or `const x = ...`, which can destructure like `let a, b = 1, 2`.
+    /// The bindings only live until the end of the enclosing block, and constants can't be reassigned.
+    Let {
+        pattern: Pattern,
+        value: Expr,
+        constant: bool,
+    },
     If {
         condition: Expr,
         body: Vec<Statement>,
         else_body: Vec<Statement>,
     },
+    /// Runs the body of the first arm whose pattern matches the subject
+    Match {
+        subject: Expr,
+        arms: Vec<MatchArm>,
+    },
+    /// Runs the body once for each value of an iterable, like a list or a range.
+    /// The values are destructured with the pattern, as in `for (i, x) in pairs { ... }`.
+    For {
+        pattern: Pattern,
+        iterable: Expr,
+        body: Vec<Statement>,
+    },
+    /// `assert condition, "message"`, stopping the program if the condition is false.
+    /// The line and source of the assertion are kept to report where it failed.
+    Assert {
+        condition: Expr,
+        message: Option<Expr>,
+        line: usize,
+        source: String,
+    },
+    /// `defer expr`, evaluating the expression when the enclosing block is left.
+    /// Deferred expressions run in reverse order, even if the block returns early or fails.
+    Defer(Expr),
+    /// `with resource as name { ... }`, entering the resource before the body and exiting it afterwards,
+    /// even if the body returns early or fails. The name is bound to the value returned when entering.
+    With {
+        resource: Expr,
+        binding: Option<Pattern>,
+        body: Vec<Statement>,
+    },
+    /// Macro declaration, like `macro square(x) { x * x }`.
+    /// Macros are expanded while parsing, so their declarations are left out of the parsed blocks.
+    Macro {
+        name: String,
+        params: Vec<String>,
+        body: Vec<Statement>,
+    },
+    /// Enum declaration, like `enum Status { Pending, Done(result), Failed(error) }`
+    Enum {
+        name: String,
+        variants: Vec<EnumVariant>,
+    },
+    /// Class declaration, like `class Money(amount, currency) implements Comparable { fn compare(self, other) { ... } }`.
+    /// The fields are given in order when creating an instance: `Money(5, "EUR")`.
+    /// The static fields are evaluated when the class is declared.
+    Class {
+        name: String,
+        fields: Vec<String>,
+        interfaces: Vec<String>,
+        methods: Vec<Method>,
+        statics: Vec<(String, Expr)>,
+    },
+    /// Assignment to a field, like `self.amount = 5` or `Money.rate = 1.1`
+    SetField {
+        object: Expr,
+        field: String,
+        value: Expr,
+    },
+    /// Interface declaration, listing the methods the classes implementing it must have:
+    /// `interface Comparable { fn compare(self, other) }`
+    Interface {
+        name: String,
+        methods: Vec<MethodSignature>,
+    },
+    /// A statement with the position where it starts in the input.
+    /// The parser only wraps the statements with their position when `Parser::positions` is set.
+    Located {
+        pos: usize,
+        statement: Box<Statement>,
+    },
+}
+
+impl Statement {
+    /// The statement, without the position wrapping it if there is one
+    pub fn unlocated(&self) -> &Statement {
+        match self {
+            Statement::Located { statement, .. } => statement.unlocated(),
+            statement => statement,
+        }
+    }
+}
+
+/// A variant of an enum, with the names of its payload fields (empty for a variant without payload)
+#[derive(Debug, PartialEq, Clone)]
+pub struct EnumVariant {
+    pub name: String,
+    pub fields: Vec<String>,
 }
 
 impl Parser {
     // Parsing functions
     pub fn parse_statement(&mut self) -> Result<Statement, Error> {
+        let pos = self.token_pos;
+        let statement = self.parse_bare_statement()?;
+        if self.positions {
+            return Ok(Statement::Located {
+                pos,
+                statement: Box::new(statement),
+            });
+        }
+        Ok(statement)
+    }
+
+    fn parse_bare_statement(&mut self) -> Result<Statement, Error> {
         match &self.current_token {
             Some(tokens::Token::Identifier(key)) => {
                 let key = key.clone();
                 self.parse_identifier(key)
             }
             Some(tokens::Token::LBrace) => self.parse_block().map(Statement::Block),
+            Some(tokens::Token::LParen) | Some(tokens::Token::LBracket) => {
+                match self.try_parse_destructure()? {
+                    Some(statement) => Ok(statement),
+                    None => self.parse_expr().map(Statement::Expr),
+                }
+            }
             _ => self.parse_expr().map(Statement::Expr),
         }
     }
@@ -41,26 +162,110 @@ impl Parser {
             "fn" => self.parse_fn(),
             "return" => self.parse_return(),
             "if" => self.parse_if(),
+            "match" => {
+                let (subject, arms) = self.parse_match()?;
+                Ok(Statement::Match { subject, arms })
+            }
+            "enum" => self.parse_enum(),
+            "class" => self.parse_class(),
+            "interface" => self.parse_interface(),
+            "for" => self.parse_for(),
+            "assert" => self.parse_assert(),
+            "defer" => {
+                self.next_token();
+                self.parse_expr().map(Statement::Defer)
+            }
+            "with" => self.parse_with(),
+            "macro" => self.parse_macro(),
+            _ if matches!(self.next_token, Some(tokens::Token::Bang)) => {
+                self.parse_macro_call().map(Statement::Block)
+            }
+            "let" => self.parse_let(false),
+            "const" => self.parse_let(true),
             _ if matches!(self.next_token, Some(tokens::Token::Assign)) => self.parse_assign(key),
-            _ => self.parse_expr().map(Statement::Expr),
+            _ if matches!(
+                self.next_token,
+                Some(tokens::Token::Comma)
+                    | Some(tokens::Token::LParen)
+                    | Some(tokens::Token::Colon)
+            ) =>
+            {
+                match self.try_parse_destructure()? {
+                    Some(statement) => Ok(statement),
+                    None => self.parse_expr().map(Statement::Expr),
+                }
+            }
+            _ => self.parse_expr_or_set_field(),
         }
     }
 
+    /// Parse an expression used as a statement, or an assignment to a field if it is followed by `=`
+    fn parse_expr_or_set_field(&mut self) -> Result<Statement, Error> {
+        let expr = self.parse_expr()?;
+        if self.current_token != Some(tokens::Token::Assign) {
+            return Ok(Statement::Expr(expr));
+        }
+        let Expr::Acessor(mut parts) = expr else {
+            return Err(self.error(errors::ErrorType::UnexpectedToken(tokens::Token::Assign)));
+        };
+        let field = match parts.pop() {
+            Some(Expr::Identifier(field)) => field,
+            _ => return Err(self.error(errors::ErrorType::UnexpectedToken(tokens::Token::Assign))),
+        };
+        let object = if parts.len() == 1 {
+            parts.remove(0)
+        } else {
+            Expr::Acessor(parts)
+        };
+        self.next_token();
+        let value = self.parse_tuple_or_expr()?;
+        Ok(Statement::SetField {
+            object,
+            field,
+            value,
+        })
+    }
+
     pub fn parse_if(&mut self) -> Result<Statement, Error> {
+        let (condition, body, else_body) = self.parse_if_branches(Self::parse_if)?;
+        Ok(Statement::If {
+            condition,
+            body,
+            else_body,
+        })
+    }
+
+    /// Parse an if used as a value, like `if a > b { a } else { b }`
+    pub fn parse_if_expr(&mut self) -> Result<Expr, Error> {
+        let (condition, body, else_body) =
+            self.parse_if_branches(|parser| Ok(Statement::Expr(parser.parse_if_expr()?)))?;
+        Ok(Expr::If {
+            condition: Box::new(condition),
+            body,
+            else_body,
+        })
+    }
+
+    /// Parse `if condition { ... } else { ... }`.
+    /// An `else if` is parsed with `chain`, and becomes the only statement of the else body.
+    fn parse_if_branches(
+        &mut self,
+        chain: fn(&mut Self) -> Result<Statement, Error>,
+    ) -> Result<(Expr, Vec<Statement>, Vec<Statement>), Error> {
         self.next_token();
         let condition = self.parse_expr()?;
         let body = self.parse_block()?;
         let else_body = if self.current_token == Some(tokens::Token::Identifier("else".into())) {
             self.next_token();
-            self.parse_block()?
+            if self.current_token == Some(tokens::Token::Identifier("if".into())) {
+                vec![chain(self)?]
+            } else {
+                self.parse_block()?
+            }
         } else {
             Vec::new()
         };
-        Ok(Statement::If {
-            condition,
-            body,
-            else_body,
-        })
+        Ok((condition, body, else_body))
     }
 
     pub fn parse_fn(&mut self) -> Result<Statement, Error> {
@@ -81,46 +286,36 @@ impl Parser {
         }
 
         self.next_token();
+        let params = self.parse_pattern_list(tokens::Token::RParen)?;
+        let return_type = if self.current_token == Some(tokens::Token::Arrow) {
+            self.next_token();
+            Some(self.expect_identifier()?)
+        } else {
+            None
+        };
 
-        let mut params = Vec::new();
-        let got_comma = true;
-        while self.current_token != Some(tokens::Token::RParen) {
-            match &self.current_token {
-                Some(tokens::Token::Identifier(param)) => {
-                    if got_comma {
-                        params.push(param.clone());
-                        self.next_token();
-                    } else {
-                        return Err(
-                            self.error(errors::ErrorType::ExpectedToken(tokens::Token::Comma))
-                        );
-                    }
-                }
-                Some(tokens::Token::Comma) => {
-                    self.next_token();
-                    continue;
-                }
-                Some(_) => {
-                    return Err(self.error(errors::ErrorType::ExpectedToken(
-                        tokens::Token::Identifier("".into()),
-                    )))
-                }
-                None => {
-                    return Err(self.error(errors::ErrorType::UnexpectedEndOfFile));
-                }
-            }
-        }
-
-        self.next_token();
-
-        let body = self.parse_block()?;
-        Ok(Statement::Fn { name, params, body })
+        // The parameters live in their own scope, so they shadow outer constants
+        self.scopes.push(
+            params
+                .iter()
+                .flat_map(Pattern::bindings)
+                .map(|param| (param, false))
+                .collect(),
+        );
+        let body = self.parse_block();
+        self.scopes.pop();
+        Ok(Statement::Fn {
+            name,
+            params,
+            return_type,
+            body: body?,
+        })
     }
 
     pub fn parse_block(&mut self) -> Result<Vec<Statement>, Error> {
         // Check if the next token is a LBrace
         let l_brace_pos = self.pos;
-        if let tokens::Token::LBrace = self.current_token.as_ref().unwrap() {
+        if let Some(tokens::Token::LBrace) = self.current_token {
             self.next_token();
         } else {
             return Err(Error::new(
@@ -129,24 +324,684 @@ impl Parser {
                 self.input.clone(),
             ));
         }
+        self.scopes.push(HashMap::new());
+        let in_match_arm = std::mem::replace(&mut self.in_match_arm, false);
+        let statements = self.parse_block_statements();
+        self.in_match_arm = in_match_arm;
+        self.scopes.pop();
+        let statements = statements?;
+        self.next_token();
+        Ok(statements)
+    }
+
+    fn parse_block_statements(&mut self) -> Result<Vec<Statement>, Error> {
         let mut statements = Vec::new();
         while self.current_token != Some(tokens::Token::RBrace) {
-            statements.push(self.parse_statement()?);
+            let statement = self.parse_statement()?;
+            self.push_statement(&mut statements, statement);
         }
-        self.next_token();
         Ok(statements)
     }
 
+    /// Add a parsed statement to a block, unless it only matters while parsing:
+    /// a macro declaration, or an assertion when the parser is optimizing them out
+    pub(crate) fn push_statement(&self, statements: &mut Vec<Statement>, statement: Statement) {
+        match statement.unlocated() {
+            Statement::Macro { .. } => {}
+            Statement::Assert { .. } if self.optimize => {}
+            _ => statements.push(statement),
+        }
+    }
+
+    pub fn parse_assert(&mut self) -> Result<Statement, Error> {
+        let start = self.input[..self.pos].rfind('\n').map_or(0, |i| i + 1);
+        let end = self.input[start..]
+            .find('\n')
+            .map_or(self.input.len(), |i| start + i);
+        let line = self.input[..start].matches('\n').count() + 1;
+        let source = self.input[start..end].trim().to_string();
+
+        self.next_token();
+        let condition = self.parse_expr()?;
+        let message = if self.current_token == Some(tokens::Token::Comma) {
+            self.next_token();
+            Some(self.parse_expr()?)
+        } else {
+            None
+        };
+        Ok(Statement::Assert {
+            condition,
+            message,
+            line,
+            source,
+        })
+    }
+
     pub fn parse_return(&mut self) -> Result<Statement, Error> {
         self.next_token();
-        let expr = self.parse_expr()?;
+        let expr = self.parse_tuple_or_expr()?;
         Ok(Statement::Return(expr))
     }
 
     fn parse_assign(&mut self, name: String) -> Result<Statement, Error> {
+        self.check_assignable(&name)?;
         self.next_token();
         self.next_token();
-        let value = self.parse_expr()?;
+        let value = self.parse_tuple_or_expr()?;
         Ok(Statement::Assign(name, value))
     }
+
+    /// Report an error if the name is a constant declared in one of the open scopes
+    fn check_assignable(&self, name: &str) -> Result<(), Error> {
+        let constant = self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name).copied());
+        if constant == Some(true) {
+            return Err(self.error(errors::ErrorType::AssignToConstant(name.to_string())));
+        }
+        Ok(())
+    }
+
+    /// Try to parse a destructuring assignment like `a, b = b, a`, or an annotated one like `x: String = "a"`.
+    /// If the statement turns out not to be one (e.g. it's a function call), the parser goes back
+    /// to where it was and None is returned.
+    fn try_parse_destructure(&mut self) -> Result<Option<Statement>, Error> {
+        let checkpoint = self.checkpoint();
+        let mut targets = Vec::new();
+        loop {
+            match self.parse_pattern() {
+                Ok(target) => targets.push(target),
+                Err(_) => {
+                    self.restore(checkpoint);
+                    return Ok(None);
+                }
+            }
+            if self.current_token != Some(tokens::Token::Comma) {
+                break;
+            }
+            self.next_token();
+        }
+        if self.current_token != Some(tokens::Token::Assign) {
+            self.restore(checkpoint);
+            return Ok(None);
+        }
+
+        let pattern = if targets.len() == 1 {
+            targets.remove(0)
+        } else {
+            Pattern::Tuple(targets)
+        };
+        for name in pattern.bindings() {
+            self.check_assignable(&name)?;
+        }
+        self.next_token();
+        let value = self.parse_tuple_or_expr()?;
+        Ok(Some(Statement::Destructure(pattern, value)))
+    }
+
+    /// Parse a `for pattern in iterable { body }` loop
+    pub fn parse_for(&mut self) -> Result<Statement, Error> {
+        let (pattern, iterable) = self.parse_for_head()?;
+        self.scopes.push(
+            pattern
+                .bindings()
+                .into_iter()
+                .map(|name| (name, false))
+                .collect(),
+        );
+        let body = self.parse_block();
+        self.scopes.pop();
+        Ok(Statement::For {
+            pattern,
+            iterable,
+            body: body?,
+        })
+    }
+
+    /// Parse `for pattern in iterable`, shared by for loops and comprehensions.
+    /// Several patterns separated by commas destructure a tuple: `for i, x in pairs`.
+    pub(crate) fn parse_for_head(&mut self) -> Result<(Pattern, Expr), Error> {
+        self.next_token();
+        let mut patterns = vec![self.parse_pattern()?];
+        while self.current_token == Some(tokens::Token::Comma) {
+            self.next_token();
+            patterns.push(self.parse_pattern()?);
+        }
+        let pattern = if patterns.len() == 1 {
+            patterns.remove(0)
+        } else {
+            Pattern::Tuple(patterns)
+        };
+
+        if self.current_token != Some(tokens::Token::Identifier("in".into())) {
+            return Err(
+                self.error(errors::ErrorType::ExpectedToken(tokens::Token::Identifier(
+                    "in".into(),
+                ))),
+            );
+        }
+        self.next_token();
+        let iterable = self.parse_expr()?;
+        Ok((pattern, iterable))
+    }
+
+    pub fn parse_with(&mut self) -> Result<Statement, Error> {
+        self.next_token();
+        let resource = self.parse_expr()?;
+        let binding = if self.current_token == Some(tokens::Token::Identifier("as".into())) {
+            self.next_token();
+            Some(self.parse_pattern()?)
+        } else {
+            None
+        };
+
+        self.scopes.push(
+            binding
+                .iter()
+                .flat_map(Pattern::bindings)
+                .map(|name| (name, false))
+                .collect(),
+        );
+        let body = self.parse_block();
+        self.scopes.pop();
+        Ok(Statement::With {
+            resource,
+            binding,
+            body: body?,
+        })
+    }
+
+    /// Parse an enum declaration
+    pub fn parse_enum(&mut self) -> Result<Statement, Error> {
+        self.next_token();
+        let name = self.expect_identifier()?;
+        if self.current_token != Some(tokens::Token::LBrace) {
+            return Err(self.error(errors::ErrorType::ExpectedToken(tokens::Token::LBrace)));
+        }
+        self.next_token();
+
+        let mut variants: Vec<EnumVariant> = Vec::new();
+        while self.current_token != Some(tokens::Token::RBrace) {
+            let variant = self.expect_identifier()?;
+            if variants.iter().any(|v| v.name == variant) {
+                return Err(self.error(errors::ErrorType::Redeclaration(variant)));
+            }
+            let mut fields = Vec::new();
+            if self.current_token == Some(tokens::Token::LParen) {
+                self.next_token();
+                while self.current_token != Some(tokens::Token::RParen) {
+                    fields.push(self.expect_identifier()?);
+                    match self.current_token {
+                        Some(tokens::Token::Comma) => self.next_token(),
+                        Some(tokens::Token::RParen) => {}
+                        _ => {
+                            return Err(
+                                self.error(errors::ErrorType::ExpectedToken(tokens::Token::RParen))
+                            )
+                        }
+                    }
+                }
+                self.next_token();
+            }
+            variants.push(EnumVariant {
+                name: variant,
+                fields,
+            });
+            if self.current_token == Some(tokens::Token::Comma) {
+                self.next_token();
+            }
+        }
+        self.next_token();
+
+        self.enums.insert(
+            name.clone(),
+            variants
+                .iter()
+                .map(|variant| variant.name.clone())
+                .collect(),
+        );
+        Ok(Statement::Enum { name, variants })
+    }
+
+    /// Parse an identifier and return its name
+    pub(crate) fn expect_identifier(&mut self) -> Result<String, Error> {
+        match self.current_token.clone() {
+            Some(tokens::Token::Identifier(name)) => {
+                self.next_token();
+                Ok(name)
+            }
+            None => Err(self.error(errors::ErrorType::UnexpectedEndOfFile)),
+            _ => Err(
+                self.error(errors::ErrorType::ExpectedToken(tokens::Token::Identifier(
+                    "".into(),
+                ))),
+            ),
+        }
+    }
+
+    /// Parse a `let` or `const` declaration
+    pub fn parse_let(&mut self, constant: bool) -> Result<Statement, Error> {
+        self.next_token();
+        let mut patterns = vec![self.parse_pattern()?];
+        while self.current_token == Some(tokens::Token::Comma) {
+            self.next_token();
+            patterns.push(self.parse_pattern()?);
+        }
+        let pattern = match patterns.len() {
+            1 => patterns.remove(0),
+            _ => Pattern::Tuple(patterns),
+        };
+        let scope = self
+            .scopes
+            .last_mut()
+            .expect("the parser always has a scope");
+        for name in pattern.bindings() {
+            if scope.contains_key(&name) {
+                return Err(self.error(errors::ErrorType::Redeclaration(name)));
+            }
+            scope.insert(name, constant);
+        }
+
+        if self.current_token != Some(tokens::Token::Assign) {
+            return Err(self.error(errors::ErrorType::ExpectedToken(tokens::Token::Assign)));
+        }
+        self.next_token();
+        let value = self.parse_tuple_or_expr()?;
+        Ok(Statement::Let {
+            pattern,
+            value,
+            constant,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_let_and_const() {
+        assert_eq!(
+            Parser::parse("let a = 1 const b = 2"),
+            Ok(Statement::Block(vec![
+                Statement::Let {
+                    pattern: Pattern::Binding("a".into()),
+                    value: Expr::Number(1.0),
+                    constant: false,
+                },
+                Statement::Let {
+                    pattern: Pattern::Binding("b".into()),
+                    value: Expr::Number(2.0),
+                    constant: true,
+                },
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_parse_let_tuples() {
+        assert_eq!(
+            Parser::parse("let a, b = 1, 2 let t = 1, 2"),
+            Ok(Statement::Block(vec![
+                Statement::Let {
+                    pattern: Pattern::Tuple(vec![
+                        Pattern::Binding("a".into()),
+                        Pattern::Binding("b".into()),
+                    ]),
+                    value: Expr::Tuple(vec![Expr::Number(1.0), Expr::Number(2.0)]),
+                    constant: false,
+                },
+                Statement::Let {
+                    pattern: Pattern::Binding("t".into()),
+                    value: Expr::Tuple(vec![Expr::Number(1.0), Expr::Number(2.0)]),
+                    constant: false,
+                },
+            ]))
+        );
+        assert!(Parser::parse("const a, b = 1, 2 b = 3").is_err());
+        assert!(Parser::parse("let a, a = 1, 2").is_err());
+    }
+
+    #[test]
+    fn test_assign_to_constant() {
+        assert!(Parser::parse("const a = 1 a = 2").is_err());
+        assert!(Parser::parse("const a = 1 if true { a = 2 }").is_err());
+        assert!(Parser::parse("const a = 1 fn f() { a = 2 }").is_err());
+    }
+
+    #[test]
+    fn test_shadowed_constant_can_be_assigned() {
+        assert!(Parser::parse("const a = 1 fn f(a) { a = 2 }").is_ok());
+        assert!(Parser::parse("const a = 1 { let a = 2 a = 3 }").is_ok());
+    }
+
+    #[test]
+    fn test_constant_out_of_scope() {
+        assert!(Parser::parse("{ const a = 1 } a = 2").is_ok());
+    }
+
+    #[test]
+    fn test_parse_destructuring() {
+        assert_eq!(
+            Parser::parse("a, (b, c) = 1, x"),
+            Ok(Statement::Block(vec![Statement::Destructure(
+                Pattern::Tuple(vec![
+                    Pattern::Binding("a".into()),
+                    Pattern::Tuple(vec![
+                        Pattern::Binding("b".into()),
+                        Pattern::Binding("c".into())
+                    ]),
+                ]),
+                Expr::Tuple(vec![Expr::Number(1.0), Expr::Identifier("x".into())]),
+            )]))
+        );
+        assert_eq!(
+            Parser::parse("[first, *rest] = xs"),
+            Ok(Statement::Block(vec![Statement::Destructure(
+                Pattern::List(vec![
+                    Pattern::Binding("first".into()),
+                    Pattern::Rest(Some("rest".into())),
+                ]),
+                Expr::Identifier("xs".into()),
+            )]))
+        );
+        assert!(Parser::parse("const a = 1 a, b = 1, 2").is_err());
+    }
+
+    #[test]
+    fn test_calls_are_not_destructuring() {
+        assert_eq!(
+            Parser::parse("f(a, b)"),
+            Ok(Statement::Block(vec![Statement::Expr(Expr::Call(
+                Box::new(Expr::Identifier("f".into())),
+                vec![Expr::Identifier("a".into()), Expr::Identifier("b".into())]
+            ))]))
+        );
+        assert!(matches!(
+            Parser::parse("(a + b)"),
+            Ok(Statement::Block(statements)) if matches!(statements[0], Statement::Expr(_))
+        ));
+        // A parenthesis on the next line isn't a call of the previous expression
+        assert_eq!(
+            Parser::parse("f(a)\n(x, y) = (1, 2)"),
+            Ok(Statement::Block(vec![
+                Statement::Expr(Expr::Call(
+                    Box::new(Expr::Identifier("f".into())),
+                    vec![Expr::Identifier("a".into())]
+                )),
+                Statement::Destructure(
+                    Pattern::Tuple(vec![
+                        Pattern::Binding("x".into()),
+                        Pattern::Binding("y".into())
+                    ]),
+                    Expr::Tuple(vec![Expr::Number(1.0), Expr::Number(2.0)]),
+                ),
+            ]))
+        );
+        assert!(Parser::parse("f(\n  a,\n  b\n)").is_ok());
+    }
+
+    #[test]
+    fn test_parse_fn_with_destructured_params() {
+        assert_eq!(
+            Parser::parse("fn f((x, y), z) { return x, z }"),
+            Ok(Statement::Block(vec![Statement::Fn {
+                name: "f".into(),
+                params: vec![
+                    Pattern::Tuple(vec![
+                        Pattern::Binding("x".into()),
+                        Pattern::Binding("y".into())
+                    ]),
+                    Pattern::Binding("z".into()),
+                ],
+                return_type: None,
+                body: vec![Statement::Return(Expr::Tuple(vec![
+                    Expr::Identifier("x".into()),
+                    Expr::Identifier("z".into())
+                ]))],
+            }]))
+        );
+    }
+
+    #[test]
+    fn test_parse_type_annotations() {
+        let typed = |name: &str, annotation: &str| Pattern::Typed {
+            pattern: Box::new(Pattern::Binding(name.into())),
+            annotation: annotation.into(),
+        };
+        assert_eq!(
+            Parser::parse("fn add(a: Number, b) -> Number { return a }"),
+            Ok(Statement::Block(vec![Statement::Fn {
+                name: "add".into(),
+                params: vec![typed("a", "Number"), Pattern::Binding("b".into())],
+                return_type: Some("Number".into()),
+                body: vec![Statement::Return(Expr::Identifier("a".into()))],
+            }]))
+        );
+        assert_eq!(
+            Parser::parse("x: String = \"a\""),
+            Ok(Statement::Block(vec![Statement::Destructure(
+                typed("x", "String"),
+                Expr::String("a".into()),
+            )]))
+        );
+        assert!(Parser::parse("const x = 1 x: Number = 2").is_err());
+    }
+
+    #[test]
+    fn test_parse_with_positions() {
+        let mut parser = Parser::new("x = 1\ny = 2");
+        parser.positions = true;
+        let (program, _) = parser.parse_program().unwrap();
+        let Statement::Block(statements) = program else {
+            panic!("Expected a block");
+        };
+        let positions: Vec<usize> = statements
+            .iter()
+            .map(|statement| match statement {
+                Statement::Located { pos, .. } => *pos,
+                _ => panic!("Expected a located statement"),
+            })
+            .collect();
+        assert_eq!(positions, vec![0, 6]);
+        assert_eq!(
+            statements[1].unlocated(),
+            &Statement::Assign("y".into(), Expr::Number(2.0))
+        );
+    }
+
+    #[test]
+    fn test_tuples_in_match_arms() {
+        assert_eq!(
+            Parser::parse("match x { 1 => return 1, _ => return (2, 3) }"),
+            Ok(Statement::Block(vec![Statement::Match {
+                subject: Expr::Identifier("x".into()),
+                arms: vec![
+                    crate::pattern::MatchArm {
+                        pattern: Pattern::Literal(Expr::Number(1.0)),
+                        guard: None,
+                        body: vec![Statement::Return(Expr::Number(1.0))],
+                    },
+                    crate::pattern::MatchArm {
+                        pattern: Pattern::Wildcard,
+                        guard: None,
+                        body: vec![Statement::Return(Expr::Tuple(vec![
+                            Expr::Number(2.0),
+                            Expr::Number(3.0)
+                        ]))],
+                    },
+                ],
+            }]))
+        );
+    }
+
+    #[test]
+    fn test_parse_enum() {
+        assert_eq!(
+            Parser::parse("enum Status { Pending, Done(result), Failed(code, reason) }"),
+            Ok(Statement::Block(vec![Statement::Enum {
+                name: "Status".into(),
+                variants: vec![
+                    EnumVariant {
+                        name: "Pending".into(),
+                        fields: vec![],
+                    },
+                    EnumVariant {
+                        name: "Done".into(),
+                        fields: vec!["result".into()],
+                    },
+                    EnumVariant {
+                        name: "Failed".into(),
+                        fields: vec!["code".into(), "reason".into()],
+                    },
+                ],
+            }]))
+        );
+        assert!(Parser::parse("enum Status { Pending Pending }").is_err());
+        assert!(Parser::parse("enum Status { Done(1) }").is_err());
+    }
+
+    #[test]
+    fn test_parse_for() {
+        assert_eq!(
+            Parser::parse("for i, x in pairs { }"),
+            Ok(Statement::Block(vec![Statement::For {
+                pattern: Pattern::Tuple(vec![
+                    Pattern::Binding("i".into()),
+                    Pattern::Binding("x".into())
+                ]),
+                iterable: Expr::Identifier("pairs".into()),
+                body: vec![],
+            }]))
+        );
+        assert!(Parser::parse("for x of xs { }").is_err());
+        assert!(Parser::parse("const x = 1 for x in xs { x = 2 }").is_ok());
+    }
+
+    #[test]
+    fn test_redeclaration() {
+        assert!(Parser::parse("let a = 1 const a = 2").is_err());
+        assert!(Parser::parse("let a = 1 { let a = 2 }").is_ok());
+    }
+
+    #[test]
+    fn test_parse_else_if() {
+        let ident = |name: &str| Expr::Identifier(name.into());
+        assert_eq!(
+            Parser::parse("if a { } else if b { } else { c }"),
+            Ok(Statement::Block(vec![Statement::If {
+                condition: ident("a"),
+                body: vec![],
+                else_body: vec![Statement::If {
+                    condition: ident("b"),
+                    body: vec![],
+                    else_body: vec![Statement::Expr(ident("c"))],
+                }],
+            }]))
+        );
+    }
+
+    #[test]
+    fn test_parse_conditional_expressions() {
+        let ident = |name: &str| Expr::Identifier(name.into());
+        let conditional = Statement::Assign(
+            "x".into(),
+            Expr::If {
+                condition: Box::new(ident("c")),
+                body: vec![Statement::Expr(ident("a"))],
+                else_body: vec![Statement::Expr(ident("b"))],
+            },
+        );
+        assert_eq!(
+            Parser::parse("x = if c { a } else { b }"),
+            Ok(Statement::Block(vec![conditional.clone()]))
+        );
+        assert_eq!(
+            Parser::parse("x = a if c else b"),
+            Ok(Statement::Block(vec![conditional]))
+        );
+        // Without an `else`, the `if` is the next statement
+        assert_eq!(
+            Parser::parse("x = a if c { }"),
+            Ok(Statement::Block(vec![
+                Statement::Assign("x".into(), ident("a")),
+                Statement::If {
+                    condition: ident("c"),
+                    body: vec![],
+                    else_body: vec![],
+                },
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_parse_assert() {
+        let source = "x = 1\n  assert x == 1, \"x is one\"\nassert x";
+        let Ok(Statement::Block(statements)) = Parser::parse(source) else {
+            panic!("Failed to parse assertions");
+        };
+        assert_eq!(
+            statements[1],
+            Statement::Assert {
+                condition: Expr::BinaryOp {
+                    op: tokens::Token::Equal,
+                    lhs: Box::new(Expr::Identifier("x".into())),
+                    rhs: Box::new(Expr::Number(1.0)),
+                },
+                message: Some(Expr::String("x is one".into())),
+                line: 2,
+                source: "assert x == 1, \"x is one\"".into(),
+            }
+        );
+        assert!(matches!(
+            &statements[2],
+            Statement::Assert {
+                message: None,
+                line: 3,
+                ..
+            }
+        ));
+
+        let mut parser = Parser::new(source);
+        parser.optimize = true;
+        let (program, _) = parser.parse_program().unwrap();
+        assert_eq!(
+            program,
+            Statement::Block(vec![Statement::Assign("x".into(), Expr::Number(1.0))])
+        );
+    }
+
+    #[test]
+    fn test_parse_defer_and_with() {
+        let open = Expr::Call(
+            Box::new(Expr::Identifier("open".into())),
+            vec![Expr::Identifier("path".into())],
+        );
+        let close = Expr::Call(
+            Box::new(Expr::Acessor(vec![
+                Expr::Identifier("f".into()),
+                Expr::Identifier("close".into()),
+            ])),
+            vec![],
+        );
+        assert_eq!(
+            Parser::parse("with open(path) as f { defer f.close() }"),
+            Ok(Statement::Block(vec![Statement::With {
+                resource: open.clone(),
+                binding: Some(Pattern::Binding("f".into())),
+                body: vec![Statement::Defer(close)],
+            }]))
+        );
+        assert_eq!(
+            Parser::parse("with open(path) { }"),
+            Ok(Statement::Block(vec![Statement::With {
+                resource: open,
+                binding: None,
+                body: vec![],
+            }]))
+        );
+        assert!(Parser::parse("const f = 1 with open(path) as f { f = 2 }").is_ok());
+    }
 }
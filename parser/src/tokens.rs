@@ -11,18 +11,38 @@ pub enum Token {
     RParen,
     LBrace,
     RBrace,
+    LBracket,
+    RBracket,
     Divider,
     Assign,
     Comma,
+    /// `:`, separating a key from its value in a map
+    Colon,
     Point,
+    /// `..`, building an exclusive range
+    DotDot,
+    /// `..=`, building an inclusive range
+    DotDotEq,
     SemiColon,
     GreaterThan,
     LessThan,
     NotEqual,
+    /// `!`, after the name of a macro being called
+    Bang,
     Equal,
     Modulo,
+    /// `|`, separating the alternatives of a pattern
+    Pipe,
+    /// `=>`, separating a match arm's pattern from its body
+    FatArrow,
+    /// `|>`, passing a value as the first argument of a call
+    PipeArrow,
+    /// `->`, before the return type of a function
+    Arrow,
     String(String),
     Identifier(String),
+    /// A character that doesn't start any known token, reported by the parser as an unexpected token.
+    Unknown(char),
 }
 // TODO: Add String, Boolean, and None
 
@@ -32,100 +52,138 @@ impl Token {
     /// for example if the input is "1+2", this function will return
     /// Some((Token::Number(1.0), "+2"))
     pub fn tokenize_first(input: &str) -> Option<(Token, &str)> {
-        let mut chars = input.chars();
-        while let Some(char) = chars.next() {
-            return match char {
-                '+' => (Token::Plus, chars.as_str()),
-                ',' => (Token::Comma, chars.as_str()),
-                '=' => {
-                    if let Some('=') = chars.as_str().chars().next() {
-                        chars.next();
-                        (Token::Equal, chars.as_str())
-                    } else {
-                        (Token::Assign, chars.as_str())
-                    }
+        let mut chars = Self::skip_blank(input).chars();
+        let char = chars.next()?;
+        Some(match char {
+            '+' => (Token::Plus, chars.as_str()),
+            ',' => (Token::Comma, chars.as_str()),
+            ':' => (Token::Colon, chars.as_str()),
+            '=' => {
+                if let Some('=') = chars.as_str().chars().next() {
+                    chars.next();
+                    (Token::Equal, chars.as_str())
+                } else if let Some('>') = chars.as_str().chars().next() {
+                    chars.next();
+                    (Token::FatArrow, chars.as_str())
+                } else {
+                    (Token::Assign, chars.as_str())
+                }
+            }
+            '!' => {
+                if let Some('=') = chars.as_str().chars().next() {
+                    chars.next();
+                    (Token::NotEqual, chars.as_str())
+                } else {
+                    (Token::Bang, chars.as_str())
                 }
-                '!' => {
+            }
+            '.' => {
+                if let Some('.') = chars.as_str().chars().next() {
+                    chars.next();
                     if let Some('=') = chars.as_str().chars().next() {
                         chars.next();
-                        (Token::NotEqual, chars.as_str())
+                        (Token::DotDotEq, chars.as_str())
                     } else {
-                        panic!("Unexpected character: {}", char)
+                        (Token::DotDot, chars.as_str())
                     }
+                } else {
+                    (Token::Point, chars.as_str())
+                }
+            }
+            '%' => (Token::Modulo, chars.as_str()),
+            '{' => (Token::LBrace, chars.as_str()),
+            '[' => (Token::LBracket, chars.as_str()),
+            ']' => (Token::RBracket, chars.as_str()),
+            '|' => {
+                if let Some('>') = chars.as_str().chars().next() {
+                    chars.next();
+                    (Token::PipeArrow, chars.as_str())
+                } else {
+                    (Token::Pipe, chars.as_str())
                 }
-                '.' => (Token::Point, chars.as_str()),
-                '%' => (Token::Modulo, chars.as_str()),
-                '{' => (Token::LBrace, chars.as_str()),
-                '}' => (Token::RBrace, chars.as_str()),
-                '<' => (Token::LessThan, chars.as_str()),
-                '>' => (Token::GreaterThan, chars.as_str()),
-                ';' => (Token::SemiColon, chars.as_str()),
-                '-' => (Token::Minus, chars.as_str()),
-                '*' => (Token::Star, chars.as_str()),
-                '(' => (Token::LParen, chars.as_str()),
-                '"' => {
-                    let mut string = String::new();
-                    while let Some(c) = chars.as_str().chars().next() {
-                        if c == '"' {
-                            chars.next();
-                            break;
-                        }
-                        string.push(c);
+            }
+            '}' => (Token::RBrace, chars.as_str()),
+            '<' => (Token::LessThan, chars.as_str()),
+            '>' => (Token::GreaterThan, chars.as_str()),
+            ';' => (Token::SemiColon, chars.as_str()),
+            '-' => {
+                if let Some('>') = chars.as_str().chars().next() {
+                    chars.next();
+                    (Token::Arrow, chars.as_str())
+                } else {
+                    (Token::Minus, chars.as_str())
+                }
+            }
+            '*' => (Token::Star, chars.as_str()),
+            '(' => (Token::LParen, chars.as_str()),
+            '"' => {
+                let mut string = String::new();
+                while let Some(c) = chars.as_str().chars().next() {
+                    if c == '"' {
                         chars.next();
+                        break;
                     }
-                    (Token::String(string), chars.as_str())
+                    string.push(c);
+                    chars.next();
                 }
-                ')' => (Token::RParen, chars.as_str()),
-                '/' => {
-                    if let Some('/') = chars.as_str().chars().next() {
-                        while let Some(c) = chars.as_str().chars().next() {
-                            if c == '\n' {
-                                break;
-                            }
-                            chars.next();
-                        }
-                        continue;
+                (Token::String(string), chars.as_str())
+            }
+            ')' => (Token::RParen, chars.as_str()),
+            '/' => (Token::Divider, chars.as_str()),
+            c if c.is_ascii_digit() => {
+                let mut num = String::new();
+                num.push(c);
+                let mut has_point = false;
+                while let Some(c) = chars.as_str().chars().next() {
+                    // TODO: Add support for scientific notation
+                    // TODO: Add support for hexadecimal numbers
+                    if c.is_ascii_digit() {
+                        num.push(c);
+                        chars.next();
+                    } else if c == '.' && !has_point && Self::digit_after_point(chars.as_str()) {
+                        // A point is only part of the number if a digit follows,
+                        // so `0..10` is a range and `10.abs` an accessor
+                        has_point = true;
+                        num.push(c);
+                        chars.next();
                     } else {
-                        (Token::Divider, chars.as_str())
-                    }
-                }
-                c if c.is_digit(10) => {
-                    let mut num = String::new();
-                    num.push(c);
-                    while let Some(c) = chars.as_str().chars().next() {
-                        // TODO: Add support for scientific notation
-                        // TODO: Add support for hexadecimal numbers
-                        // TODO IMPORTANT: Add support for acessors on numbers, like 10.floor
-                        if c.is_digit(10) || c == '.' {
-                            num.push(c);
-                            chars.next();
-                        } else {
-                            break;
-                        }
+                        break;
                     }
-                    let num = num.parse().unwrap();
-                    (Token::Number(num), chars.as_str())
                 }
-                c if c.is_alphabetic() => {
-                    let mut id = String::new();
-                    id.push(c);
-                    while let Some(c) = chars.as_str().chars().next() {
-                        if c.is_alphabetic() {
-                            id.push(c);
-                            chars.next();
-                        } else {
-                            break;
-                        }
+                let num = num.parse().unwrap();
+                (Token::Number(num), chars.as_str())
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut id = String::new();
+                id.push(c);
+                while let Some(c) = chars.as_str().chars().next() {
+                    if c.is_alphanumeric() || c == '_' {
+                        id.push(c);
+                        chars.next();
+                    } else {
+                        break;
                     }
-                    (Token::Identifier(id), chars.as_str())
                 }
-                ' ' => continue,
-                '\n' => continue,
-                _ => panic!("Unexpected character: {}", char),
+                (Token::Identifier(id), chars.as_str())
             }
-            .into();
+            _ => (Token::Unknown(char), chars.as_str()),
+        })
+    }
+
+    /// Skip the spaces, newlines and `//` comments at the start of the input, which separate the tokens
+    pub fn skip_blank(input: &str) -> &str {
+        let mut input = input.trim_start_matches([' ', '\n']);
+        while input.starts_with("//") {
+            let end = input.find('\n').unwrap_or(input.len());
+            input = input[end..].trim_start_matches([' ', '\n']);
         }
-        None
+        input
+    }
+
+    /// Check if the input starts with a point followed by a digit
+    fn digit_after_point(input: &str) -> bool {
+        let mut chars = input.chars();
+        chars.next() == Some('.') && chars.next().is_some_and(|c| c.is_ascii_digit())
     }
 }
 
@@ -169,4 +227,63 @@ mod tests {
         );
         assert_eq!(Token::tokenize_first(" "), None);
     }
+
+    #[test]
+    fn test_skip_blank() {
+        assert_eq!(Token::skip_blank("  \n x"), "x");
+        assert_eq!(
+            Token::skip_blank("// note\n  // other\nx // end"),
+            "x // end"
+        );
+        assert_eq!(Token::skip_blank("// only a comment"), "");
+        assert_eq!(
+            Token::tokenize_first("// note\n/ 2"),
+            Some((Token::Divider, " 2"))
+        );
+    }
+
+    #[test]
+    fn test_tokenize_ranges_and_accessors() {
+        assert_eq!(
+            Token::tokenize_first("0..10"),
+            Some((Token::Number(0.0), "..10"))
+        );
+        assert_eq!(Token::tokenize_first("..10"), Some((Token::DotDot, "10")));
+        assert_eq!(
+            Token::tokenize_first("..=10"),
+            Some((Token::DotDotEq, "10"))
+        );
+        assert_eq!(
+            Token::tokenize_first("1.5..2"),
+            Some((Token::Number(1.5), "..2"))
+        );
+        assert_eq!(
+            Token::tokenize_first("10.abs()"),
+            Some((Token::Number(10.0), ".abs()"))
+        );
+        assert_eq!(
+            Token::tokenize_first("1.2.3"),
+            Some((Token::Number(1.2), ".3"))
+        );
+    }
+
+    #[test]
+    fn test_tokenize_match_tokens() {
+        assert_eq!(Token::tokenize_first("=> 1"), Some((Token::FatArrow, " 1")));
+        assert_eq!(Token::tokenize_first("| 2"), Some((Token::Pipe, " 2")));
+        assert_eq!(
+            Token::tokenize_first("|> f"),
+            Some((Token::PipeArrow, " f"))
+        );
+        assert_eq!(Token::tokenize_first("!(x)"), Some((Token::Bang, "(x)")));
+        assert_eq!(Token::tokenize_first("-> T"), Some((Token::Arrow, " T")));
+        assert_eq!(Token::tokenize_first("- 1"), Some((Token::Minus, " 1")));
+        assert_eq!(Token::tokenize_first("!= x"), Some((Token::NotEqual, " x")));
+        assert_eq!(Token::tokenize_first("[a]"), Some((Token::LBracket, "a]")));
+        assert_eq!(Token::tokenize_first("]"), Some((Token::RBracket, "")));
+        assert_eq!(
+            Token::tokenize_first("_rest1 "),
+            Some((Token::Identifier("_rest1".to_string()), " "))
+        );
+    }
 }
@@ -1,13 +1,19 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use std::io::{self, Write};
+use std::path::PathBuf;
+use vm::permissions::{Access, VmPermissions};
 
 #[derive(Parser, Debug)]
 #[command(
     author = "Dario Le Hy <dario.lehy@ik.me>",
     version = "1.0",
-    about = "Frutta programming language CLI"
+    about = "Frutta programming language CLI",
+    after_help = "Permissions: without any permission flag, the program can use everything. \
+Given any --allow-* flag or --deny-all, it can only use what the --allow-* flags allow."
 )]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
     #[arg(help = "Input file")]
     input: Option<String>,
     #[arg(short, long)]
@@ -16,10 +22,76 @@ struct Args {
     #[arg(short, long)]
     #[arg(help = "Show execution time")]
     time: bool,
+    #[arg(short, long)]
+    #[arg(help = "Remove the assertions from the program")]
+    optimize: bool,
+    #[arg(short, long)]
+    #[arg(help = "Show the AST once the macros are expanded, without running it")]
+    expand: bool,
+    #[arg(long)]
+    #[arg(help = "Deny everything, or everything that isn't allowed by the --allow-* flags")]
+    deny_all: bool,
+    #[arg(long, value_name = "PATHS", num_args = 0.., require_equals = true, value_delimiter = ',')]
+    #[arg(help = "Allow reading files, only in these paths if given: --allow-read=./data")]
+    allow_read: Option<Vec<PathBuf>>,
+    #[arg(long, value_name = "PATHS", num_args = 0.., require_equals = true, value_delimiter = ',')]
+    #[arg(help = "Allow writing files, only in these paths if given: --allow-write=./out")]
+    allow_write: Option<Vec<PathBuf>>,
+    #[arg(long)]
+    #[arg(help = "Allow reading the environment variables")]
+    allow_env: bool,
+    #[arg(long)]
+    #[arg(help = "Allow reading the standard input")]
+    allow_input: bool,
+    #[arg(long)]
+    #[arg(help = "Allow reading the clock and sleeping")]
+    allow_time: bool,
+}
+
+impl Args {
+    /// The permissions of the program: everything without any permission flag,
+    /// otherwise only what the `--allow-*` flags allow
+    fn permissions(&self) -> VmPermissions {
+        let restricted = self.deny_all
+            || self.allow_read.is_some()
+            || self.allow_write.is_some()
+            || self.allow_env
+            || self.allow_input
+            || self.allow_time;
+        if !restricted {
+            return VmPermissions::default();
+        }
+        let access = |paths: &Option<Vec<PathBuf>>| match paths {
+            None => Access::None,
+            Some(paths) if paths.is_empty() => Access::All,
+            Some(paths) => Access::Paths(paths.clone()),
+        };
+        VmPermissions {
+            input: self.allow_input,
+            env: self.allow_env,
+            time: self.allow_time,
+            read: access(&self.allow_read),
+            write: access(&self.allow_write),
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Check the types of a program without running it
+    Check {
+        #[arg(help = "Input file")]
+        input: String,
+    },
 }
 
 fn main() {
     let args = Args::parse();
+    if let Some(Command::Check { input }) = args.command {
+        check(&input);
+        return;
+    }
+    let permissions = args.permissions();
     let input_file = args.input.or_else(|| std::env::args().nth(1));
 
     if let Some(input_file) = input_file {
@@ -27,7 +99,15 @@ fn main() {
 
         // Assuming you have a parser and VM module
         let parse_start = std::time::Instant::now();
-        let expr = parser::Parser::parse(&input);
+        // The positions are only needed to report the runtime errors, not when showing the AST
+        let expr = parse(&input, args.optimize, !(args.ast || args.expand));
+        if args.expand {
+            match expr {
+                Ok(program) => println!("{:#?}", program),
+                Err(e) => println!("Error: {:?}", e),
+            }
+            return;
+        }
         if args.ast {
             println!("{:#?}", expr);
         } else if let Err(e) = expr {
@@ -38,23 +118,61 @@ fn main() {
             return;
         }
 
-        let mut vm = vm::VM::new();
+        let mut vm = vm::VM::new(permissions);
 
         let exec_start = std::time::Instant::now();
         if let Ok(expr) = expr {
-            vm.exec_statement(&expr);
+            let result = vm.exec_source(&expr, &input);
             if args.time {
                 println!("Parsing time: {:?}", parse_start.elapsed());
                 println!("Execution time: {:?}", exec_start.elapsed());
             }
+            if let Err(error) = result {
+                println!("Error: {}", error);
+                std::process::exit(1);
+            }
         }
     } else {
-        run_repl();
+        run_repl(args.optimize, permissions);
+    }
+}
+
+/// Parse a program, printing the warnings found in it
+fn parse(
+    input: &str,
+    optimize: bool,
+    positions: bool,
+) -> Result<parser::statement::Statement, parser::errors::Error> {
+    let mut parser = parser::Parser::new(input);
+    parser.optimize = optimize;
+    parser.positions = positions;
+    let (program, warnings) = parser.parse_program()?;
+    for warning in warnings {
+        println!("Warning: {:?}", warning);
+    }
+    Ok(program)
+}
+
+/// Report the type errors of a program, exiting with an error code if there are any
+fn check(input_file: &str) {
+    let input = std::fs::read_to_string(input_file).expect("Failed to read input file");
+    match parser::check::check(&input) {
+        Ok(errors) if errors.is_empty() => println!("No type errors found"),
+        Ok(errors) => {
+            for error in &errors {
+                println!("Error: {:?}", error);
+            }
+            std::process::exit(1);
+        }
+        Err(e) => {
+            println!("Error: {:?}", e);
+            std::process::exit(1);
+        }
     }
 }
 
-fn run_repl() {
-    let mut vm = vm::VM::new();
+fn run_repl(optimize: bool, permissions: VmPermissions) {
+    let mut vm = vm::VM::new(permissions);
     let stdin = io::stdin();
     let mut stdout = io::stdout();
 
@@ -69,10 +187,12 @@ fn run_repl() {
             continue;
         }
 
-        let expr = parser::Parser::parse(&input);
+        let expr = parse(&input, optimize, true);
         match expr {
             Ok(expr) => {
-                vm.exec_statement(&expr);
+                if let Err(error) = vm.exec_source(&expr, &input) {
+                    println!("Error: {}", error);
+                }
             }
             Err(e) => {
                 println!("Error: {:?}", e);
@@ -80,3 +200,54 @@ fn run_repl() {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn permissions(flags: &[&str]) -> VmPermissions {
+        let args = ["frutta"].iter().chain(flags).chain(&["script.fru"]);
+        Args::try_parse_from(args).unwrap().permissions()
+    }
+
+    #[test]
+    fn test_permission_flags() {
+        assert_eq!(permissions(&[]), VmPermissions::default());
+        assert_eq!(permissions(&["--deny-all"]), VmPermissions::none());
+
+        // Any --allow-* flag denies what it doesn't allow, with or without --deny-all
+        for deny_all in [&[][..], &["--deny-all"]] {
+            let with = |flags: &[&str]| permissions(&[deny_all, flags].concat());
+            assert_eq!(
+                with(&["--allow-env"]),
+                VmPermissions {
+                    env: true,
+                    ..VmPermissions::none()
+                }
+            );
+            assert_eq!(
+                with(&["--allow-input", "--allow-time"]),
+                VmPermissions {
+                    input: true,
+                    time: true,
+                    ..VmPermissions::none()
+                }
+            );
+            assert_eq!(
+                with(&["--allow-read=./data,./config", "--allow-write"]),
+                VmPermissions {
+                    read: Access::Paths(vec!["./data".into(), "./config".into()]),
+                    write: Access::All,
+                    ..VmPermissions::none()
+                }
+            );
+            assert_eq!(
+                with(&["--allow-write=./out"]),
+                VmPermissions {
+                    write: Access::Paths(vec!["./out".into()]),
+                    ..VmPermissions::none()
+                }
+            );
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    class::Class,
+    errors::{raise, ErrorKind, RuntimeError},
+    list::ListInstance,
+    map::MapInstance,
+    tuple::TupleInstance,
+    Value,
+};
+
+/// The class of `true` and `false`. Calling it converts a value: `Boolean(0)` is false.
+#[derive(Debug)]
+pub struct BooleanClass;
+
+impl BooleanClass {
+    /// Whether a value counts as true: None, zero, and empty strings and collections are false
+    pub fn truthy(value: &Value) -> bool {
+        let instance = match value {
+            Value::None => return false,
+            Value::Boolean(b) => return *b,
+            Value::Number(n) => return *n != 0.0,
+            Value::String(string) => return !string.is_empty(),
+            Value::ClassInstance(instance) => instance.as_any(),
+            Value::Class(_) | Value::Function(_) => return true,
+        };
+        if let Some(list) = instance.downcast_ref::<ListInstance>() {
+            !list.items.borrow().is_empty()
+        } else if let Some(tuple) = instance.downcast_ref::<TupleInstance>() {
+            !tuple.items.is_empty()
+        } else if let Some(map) = instance.downcast_ref::<MapInstance>() {
+            !map.entries.borrow().is_empty()
+        } else {
+            true
+        }
+    }
+}
+
+impl Class for BooleanClass {
+    fn name(&self) -> &str {
+        "Boolean"
+    }
+
+    fn construct(&self, args: Vec<Rc<RefCell<Value>>>) -> Result<Value, RuntimeError> {
+        let value = match args.as_slice() {
+            [] => false,
+            [value] => BooleanClass::truthy(&value.borrow()),
+            _ => raise(
+                ErrorKind::Arity,
+                format!("Boolean expects at most 1 argument, got {}", args.len()),
+            )?,
+        };
+        Ok(Value::Boolean(value))
+    }
+}
@@ -1,13 +1,81 @@
 use std::{cell::RefCell, rc::Rc};
 
-use crate::{MagicMethod, Value};
+use crate::{
+    errors::{raise, unsupported, ErrorKind, RuntimeError},
+    gc::Tracer,
+    Function, MagicMethod, Value,
+};
 
+/// The items of an iterable instance, whose computation can fail, like the elements of a generator
+pub type Items = Box<dyn Iterator<Item = Result<Rc<RefCell<Value>>, RuntimeError>>>;
+
+/// A class, bound to its name in the scripts. Its static members are accessed on it: `Number.MAX`, `Std.print`.
 pub trait Class: std::fmt::Debug {
-    fn create_instance(&self) -> Rc<dyn ClassInstance>;
+    /// The name of the class, as written in the scripts
+    fn name(&self) -> &str;
+    /// A static field or method of the class, like `Number.MAX` or `Number.parse`
+    fn get_static(&self, _name: &str) -> Option<Rc<RefCell<Value>>> {
+        None
+    }
+    /// Assign a static field, like `Money.rate = 1.1`. Returns false if the class has no such field.
+    fn set_static(&self, _name: &str, _value: Rc<RefCell<Value>>) -> bool {
+        false
+    }
+    /// Create an instance from the arguments the class is called with, like `Money(5, "EUR")`.
+    /// By default, the class can't be called.
+    fn construct(&self, _args: Vec<Rc<RefCell<Value>>>) -> Result<Value, RuntimeError> {
+        raise(
+            ErrorKind::Type,
+            format!("Class '{}' cannot be called", self.name()),
+        )
+    }
+    /// A method of the values of the class that aren't class instances, like `abs` in `x.abs()` for numbers.
+    /// It is called with the value as its first argument.
+    fn method(&self, _name: &str) -> Option<Function> {
+        None
+    }
+    /// Visit the objects the class refers to, for the cycle collector
+    fn trace(&self, _tracer: &mut Tracer) {}
 }
 
 pub trait ClassInstance: std::fmt::Debug {
+    /// The name of the instance's class, as written in the scripts
+    fn class_name(&self) -> &str;
     fn get_field(&self, name: &str) -> Option<Rc<RefCell<Value>>>;
-    fn call_magic(&self, method: MagicMethod, args: Vec<Rc<RefCell<Value>>>) -> Rc<RefCell<Value>>;
+    /// Assign a field, like `p.x = 1`. Returns false if the instance has no such field.
+    fn set_field(&self, _name: &str, _value: Rc<RefCell<Value>>) -> Result<bool, RuntimeError> {
+        Ok(false)
+    }
+    /// Apply an operator, the instance being the first of the arguments.
+    /// `Enter` and `Exit` are only given the instance, and `Exit` whether the `with` body failed.
+    /// By default, instances are only equal to themselves, and other operators fail.
+    fn call_magic(
+        &self,
+        method: MagicMethod,
+        args: Vec<Rc<RefCell<Value>>>,
+    ) -> Result<Rc<RefCell<Value>>, RuntimeError> {
+        match method {
+            MagicMethod::Equal | MagicMethod::NotEqual => {
+                let same = std::ptr::addr_eq(self.as_any(), args[1].borrow().as_any());
+                Ok(Rc::new(RefCell::new(Value::Boolean(
+                    same == (method == MagicMethod::Equal),
+                ))))
+            }
+            _ => unsupported(method, self.class_name(), &args),
+        }
+    }
     fn as_any(&self) -> &dyn std::any::Any;
+    /// The positional fields of the instance, used by class patterns like `Point(x, y)`.
+    /// Returns None if the instance can't be destructured.
+    fn destructure(&self) -> Option<Vec<Rc<RefCell<Value>>>> {
+        None
+    }
+    /// Iterate over the values of the instance, used by `for` loops.
+    /// Returns None if the instance isn't iterable.
+    fn iterate(&self) -> Option<Items> {
+        None
+    }
+    /// Visit the objects the instance refers to, for the cycle collector.
+    /// Instances holding mutable values must keep them in a tracked container, like the items of a list.
+    fn trace(&self, _tracer: &mut Tracer) {}
 }
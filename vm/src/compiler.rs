@@ -0,0 +1,1056 @@
+use std::rc::Rc;
+
+use parser::{
+    class::MethodKind,
+    expr::{Comprehension, Expr},
+    pattern::{MatchArm, Pattern},
+    statement::Statement,
+    tokens::Token,
+};
+
+use crate::{
+    class::Class,
+    enum_::EnumInstance,
+    errors::{ErrorKind, RuntimeError, Span},
+    resolver::{Capture, Resolution, Resolver, Variable},
+    script_class::InterfaceInstance,
+    string, Globals, MagicMethod, Value,
+};
+use std::collections::HashMap;
+
+/// An instruction of the VM. Expressions push their value on the stack, statements leave it as they found it.
+/// Jumps hold the index of the instruction they go to.
+#[derive(Debug, Clone)]
+pub(crate) enum Op {
+    /// Push a constant of the chunk
+    Constant(u32),
+    /// Push the value of a slot of the running function. The name is kept for the error if it isn't assigned yet.
+    LoadLocal {
+        slot: u32,
+        name: Rc<str>,
+    },
+    /// Push the value of a variable captured by the running function
+    LoadUpvalue {
+        index: u32,
+        name: Rc<str>,
+    },
+    LoadGlobal(u32),
+    /// Pop a value and store it in a variable
+    Store(Target),
+    /// Pop a value and assign the names bound by a pattern of the chunk
+    Destructure(u32),
+    Pop,
+    Jump(u32),
+    /// Pop a value and jump unless it is `true`
+    JumpIfFalse(u32),
+    /// Pop two values and push the result of an operator
+    Binary(MagicMethod),
+    /// Pop a value and push the result of an operator whose right side is a constant of the chunk, like `n - 1`
+    BinaryConstant {
+        method: MagicMethod,
+        constant: u32,
+    },
+    /// Pop the arguments, then the value called, and push the result
+    Call(u32),
+    /// Pop a value and push one of its members
+    GetMember(Rc<str>),
+    /// Pop a value, then an object, and assign the member of the object
+    SetMember(Rc<str>),
+    /// Pop items and push a list, a tuple or a map (whose items are keys followed by values)
+    List(u32),
+    Tuple(u32),
+    Map(u32),
+    /// Pop the end, then the start, and push a range
+    Range {
+        inclusive: bool,
+    },
+    /// Pop a value and push whether it is an instance of a class or implements an interface.
+    /// If the name is bound to a variable, its value is popped first, to check the interfaces.
+    Is {
+        class: Rc<str>,
+        bound: bool,
+    },
+    /// Run a block of the chunk in a new scope and push its value, for the blocks used as expressions
+    Block(u32),
+    /// Push a function of the chunk, capturing the variables visible where it is declared
+    Closure(u32),
+    /// Pop the values of the static fields, then the interfaces, and push a class of the chunk
+    Class(u32),
+    /// Pop an iterable and start iterating over it
+    Iter,
+    /// Push the next item of the innermost iteration, or end it and jump if it is done
+    Next(u32),
+    /// Pop a value and declare the names bound by a pattern of the chunk
+    Bind(u32),
+    /// Match the value on top of the stack against a pattern of the chunk.
+    /// If it matches, declare the names bound by the pattern, otherwise jump.
+    MatchArm {
+        pattern: u32,
+        otherwise: u32,
+    },
+    /// Pop an item and add it to the list below it, for list comprehensions
+    ListPush,
+    /// Pop a value and a key and add them to the map below them, for map comprehensions
+    MapInsert,
+    /// Pop an iterable and push a generator of the chunk over it
+    Generator(u32),
+    /// Pop a value and return it, running the pending cleanups
+    Return,
+    /// Pop a value and return it from the function running the code, leaving first the given number
+    /// of blocks used as expressions that the code is nested in
+    ReturnFromBlocks(u32),
+    /// Register a block of the chunk to run when the current statement list ends
+    Defer(u32),
+    /// Start a statement list with deferred expressions
+    BeginCleanups,
+    /// Run the expressions deferred since the matching `BeginCleanups`, the last first
+    EndCleanups,
+    /// Pop a resource, enter it and push the value bound by `with ... as`
+    Enter,
+    /// Exit the innermost resource entered
+    Exit,
+    /// Check an assertion of the chunk, popping its condition or both sides of its comparison
+    Assert(u32),
+    /// Fail on an accessor that isn't a name or a call, like the `2` of `x.2`
+    InvalidAccessor,
+}
+
+/// Compiled code: the instructions and the data they refer to
+#[derive(Default)]
+pub(crate) struct Chunk {
+    pub code: Vec<Op>,
+    pub constants: Vec<Value>,
+    /// The blocks evaluated apart from the main code: blocks used as expressions, deferred expressions,
+    /// assertion messages and the literals of patterns
+    pub blocks: Vec<Rc<Chunk>>,
+    pub functions: Vec<Rc<FunctionCode>>,
+    pub patterns: Vec<Rc<CompiledPattern>>,
+    pub classes: Vec<Rc<ClassCode>>,
+    pub generators: Vec<Rc<GeneratorCode>>,
+    pub asserts: Vec<AssertCode>,
+    /// Whether the code defers expressions or enters resources, which must be cleaned up even if it fails
+    pub has_cleanups: bool,
+    /// The name of the function whose body this is, or `<program>`, for the stack traces of errors.
+    /// Blocks evaluated apart from the main code have none, their errors being reported in the code around them.
+    pub name: Option<Rc<str>>,
+    /// The source the chunk is compiled from, if known, to report the line where errors happen
+    pub source: Option<Rc<str>>,
+    /// The position in the source of each statement, with the index of its first instruction, in order
+    pub positions: Vec<(u32, usize)>,
+}
+
+impl Chunk {
+    /// The position in the source of the statement the instruction at `ip` belongs to
+    pub fn position(&self, ip: usize) -> Option<usize> {
+        let index = self
+            .positions
+            .partition_point(|(start, _)| *start as usize <= ip);
+        index.checked_sub(1).map(|index| self.positions[index].1)
+    }
+}
+
+/// Where a value is stored by an assignment, a declaration or a pattern
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Target {
+    /// A slot of the running function, assigned in place: the closures that captured it see the new value
+    Local(u32),
+    /// A slot of the running function, bound anew by a declaration: the closures that captured it keep
+    /// the previous value, like the closures created by the previous iterations of a loop
+    Declare(u32),
+    Upvalue(u32),
+    Global(u32),
+}
+
+/// How the names of a pattern are bound
+#[derive(Clone, Copy)]
+enum Bind {
+    /// Assigned like by `name = ...`
+    Assign,
+    /// Declared in the innermost scope
+    Declare,
+    /// Declared in the innermost scope as constants, like by `const`
+    Constant,
+}
+
+/// A function declared with `fn`, a method of a class, or the top level of a program
+pub struct FunctionCode {
+    /// The name the function is bound to in its own body, in its first slot
+    pub(crate) name: Rc<str>,
+    pub(crate) params: Vec<CompiledPattern>,
+    /// Whether the parameters are names bound in order to the slots after the function's own,
+    /// which the function and its arguments are moved to as they are
+    pub(crate) plain_params: bool,
+    pub(crate) chunk: Rc<Chunk>,
+    /// The number of slots of its variables
+    pub(crate) slots: u32,
+    /// The variables of the code around it that it captures when it is created
+    pub(crate) captures: Vec<Capture>,
+}
+
+/// A class declaration. The values of its interfaces and static fields are computed by the code declaring it.
+pub(crate) struct ClassCode {
+    pub name: String,
+    pub fields: Vec<String>,
+    pub interfaces: Vec<String>,
+    pub methods: Vec<(MethodKind, String, Rc<FunctionCode>)>,
+    pub statics: Vec<String>,
+}
+
+/// The lazy part of a generator, evaluated for each item of its iterable as a function of its own
+pub(crate) struct GeneratorCode {
+    pub pattern: CompiledPattern,
+    pub condition: Option<Rc<Chunk>>,
+    pub element: Rc<Chunk>,
+    pub slots: u32,
+    pub captures: Vec<Capture>,
+}
+
+pub(crate) struct AssertCode {
+    /// The operator of the condition when it is a comparison, whose sides are shown if it fails
+    pub comparison: Option<MagicMethod>,
+    pub message: Option<Rc<Chunk>>,
+    pub line: usize,
+    pub source: String,
+}
+
+/// A pattern whose literals are compiled, along with the pattern as written for the error messages
+pub(crate) struct CompiledPattern {
+    pub source: Pattern,
+    pub code: PatternCode,
+}
+
+pub(crate) enum PatternCode {
+    Wildcard,
+    /// A literal, evaluated by its chunk when matching
+    Literal(Rc<Chunk>),
+    Binding(Target),
+    Alternatives(Vec<PatternCode>),
+    Class {
+        name: String,
+        fields: Vec<PatternCode>,
+    },
+    List(Vec<PatternCode>),
+    Tuple(Vec<PatternCode>),
+    Rest(Option<Target>),
+}
+
+impl PatternCode {
+    pub fn is_rest(&self) -> bool {
+        matches!(self, PatternCode::Rest(_))
+    }
+}
+
+/// Compile a whole program, as returned by `parser::Parser::parse`.
+/// Its names are resolved to the global variables, which are added to `globals`, and to the builtin classes.
+/// With its source, the errors point at the statements the parser located, with `Parser::positions`.
+pub(crate) fn compile_program(
+    program: &Statement,
+    source: Option<&str>,
+    globals: &mut Globals,
+    classes: &HashMap<String, Rc<dyn Class>>,
+) -> Result<FunctionCode, RuntimeError> {
+    let known = globals.variables.len();
+    let mut compiler = Compiler {
+        chunk: Chunk {
+            name: Some("<program>".into()),
+            source: source.map(Rc::from),
+            ..Chunk::default()
+        },
+        resolver: Resolver::new(globals, classes),
+        position: None,
+        error: None,
+        blocks: 0,
+    };
+    let statements = match program {
+        Statement::Block(statements) => statements.as_slice(),
+        statement => std::slice::from_ref(statement),
+    };
+    compiler.resolver.hoist(statements);
+    compiler.statements(statements);
+    let (slots, _) = compiler.resolver.end_function();
+    let Compiler { chunk, error, .. } = compiler;
+    if let Some(error) = error {
+        // The program never runs, so the global variables it declares don't exist
+        globals.truncate(known);
+        return Err(error);
+    }
+    Ok(FunctionCode {
+        name: "<program>".into(),
+        params: Vec::new(),
+        plain_params: true,
+        chunk: Rc::new(chunk),
+        slots,
+        captures: Vec::new(),
+    })
+}
+
+struct Compiler<'a> {
+    chunk: Chunk,
+    resolver: Resolver<'a>,
+    /// The position of the statement or call being compiled, if the parser located it
+    position: Option<usize>,
+    /// The first error found, like a name that couldn't be resolved. The compilation goes on, but the program doesn't run.
+    error: Option<RuntimeError>,
+    /// The number of blocks used as expressions that the code being compiled is nested in, in its function
+    blocks: u32,
+}
+
+impl Compiler<'_> {
+    /// Compile code apart from the chunk, from the same source, returning its chunk
+    fn nested(&mut self, compile: impl FnOnce(&mut Self)) -> Chunk {
+        let chunk = Chunk {
+            source: self.chunk.source.clone(),
+            ..Chunk::default()
+        };
+        let outer = std::mem::replace(&mut self.chunk, chunk);
+        compile(self);
+        std::mem::replace(&mut self.chunk, outer)
+    }
+
+    /// Record the position of a statement located by the parser, for the instructions compiled next
+    fn locate(&mut self, statement: &Statement) {
+        if let Statement::Located { pos, .. } = statement {
+            self.mark(*pos);
+        }
+    }
+
+    /// Record a position in the source for the instructions compiled next
+    fn mark(&mut self, pos: usize) {
+        let start = self.here();
+        match self.chunk.positions.last_mut() {
+            Some((last, last_pos)) if *last == start => *last_pos = pos,
+            _ => self.chunk.positions.push((start, pos)),
+        }
+        self.position = Some(pos);
+    }
+
+    /// Report an error found while compiling, at the statement being compiled
+    fn fail(&mut self, mut error: RuntimeError) {
+        if self.error.is_none() {
+            error.span = self
+                .chunk
+                .source
+                .as_deref()
+                .zip(self.position)
+                .map(|(source, pos)| Span::at(source, pos));
+            self.error = Some(error);
+        }
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        self.chunk.code.push(op);
+        self.chunk.code.len() - 1
+    }
+
+    /// The index of the next instruction, to jump to it
+    fn here(&self) -> u32 {
+        self.chunk.code.len() as u32
+    }
+
+    /// Make the jump at `at` go to the next instruction
+    fn patch(&mut self, at: usize) {
+        let target = self.here();
+        match &mut self.chunk.code[at] {
+            Op::Jump(to)
+            | Op::JumpIfFalse(to)
+            | Op::Next(to)
+            | Op::MatchArm { otherwise: to, .. } => *to = target,
+            op => unreachable!("{:?} is not a jump", op),
+        }
+    }
+
+    fn constant(&mut self, value: Value) {
+        self.chunk.constants.push(value);
+        let index = self.chunk.constants.len() as u32 - 1;
+        self.emit(Op::Constant(index));
+    }
+
+    /// Push the value of the variable or builtin class a name designates
+    fn load(&mut self, name: &str) {
+        match self.resolver.resolve(name) {
+            Ok(Resolution::Variable(variable)) => self.load_variable(variable, name),
+            Ok(Resolution::Class(class)) => self.constant(Value::Class(class)),
+            Err(error) => {
+                self.fail(error);
+                self.constant(Value::None);
+            }
+        }
+    }
+
+    fn load_variable(&mut self, variable: Variable, name: &str) {
+        let name = name.into();
+        self.emit(match variable {
+            Variable::Local(slot) => Op::LoadLocal { slot, name },
+            Variable::Upvalue(index) => Op::LoadUpvalue { index, name },
+            Variable::Global(index) => Op::LoadGlobal(index),
+        });
+    }
+
+    /// The variable assigned by `name = ...`
+    fn assign(&mut self, name: &str) -> Target {
+        let variable = self.resolver.assign(name);
+        self.target(variable, Target::Local)
+    }
+
+    /// The variable declared by `let`, `const`, a parameter or a pattern binding a name
+    fn declare(&mut self, name: &str, constant: bool) -> Target {
+        let variable = self.resolver.declare(name, constant);
+        self.target(variable, Target::Declare)
+    }
+
+    /// The variable of the function bound by a declaration like `fn` or `class`
+    fn define(&mut self, name: &str) -> Target {
+        let variable = self.resolver.define(name);
+        self.target(Ok(variable), Target::Local)
+    }
+
+    fn target(
+        &mut self,
+        variable: Result<Variable, RuntimeError>,
+        local: impl FnOnce(u32) -> Target,
+    ) -> Target {
+        match variable {
+            Ok(Variable::Local(slot)) => local(slot),
+            Ok(Variable::Upvalue(index)) => Target::Upvalue(index),
+            Ok(Variable::Global(index)) => Target::Global(index),
+            Err(error) => {
+                self.fail(error);
+                // The program doesn't run, so the value is never stored
+                Target::Declare(0)
+            }
+        }
+    }
+
+    /// Compile a pattern whose names are declared, like the one of a `for` loop, in the innermost scope
+    fn pattern(&mut self, pattern: &Pattern) -> u32 {
+        let pattern = self.compile_pattern(pattern, Bind::Declare);
+        self.chunk.patterns.push(Rc::new(pattern));
+        self.chunk.patterns.len() as u32 - 1
+    }
+
+    /// Compile a block evaluated apart from the main code, returning its index
+    fn block(&mut self, chunk: Chunk) -> u32 {
+        self.chunk.blocks.push(Rc::new(chunk));
+        self.chunk.blocks.len() as u32 - 1
+    }
+
+    /// Compile statements executed in order, running the expressions they defer once they are done
+    fn statements(&mut self, statements: &[Statement]) {
+        let defers = statements
+            .iter()
+            .any(|statement| matches!(statement.unlocated(), Statement::Defer(_)));
+        if defers {
+            self.emit(Op::BeginCleanups);
+        }
+        for statement in statements {
+            self.statement(statement);
+        }
+        if defers {
+            self.emit(Op::EndCleanups);
+        }
+    }
+
+    /// Compile statements in their own scope, for the variables they declare with `let`
+    fn scoped_statements(&mut self, statements: &[Statement]) {
+        self.resolver.begin_scope();
+        self.statements(statements);
+        self.resolver.end_scope();
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Block(statements) => self.scoped_statements(statements),
+            Statement::Assign(name, expr) => {
+                self.expr(expr);
+                let target = self.assign(name);
+                self.emit(Op::Store(target));
+            }
+            Statement::Destructure(pattern, expr) => {
+                self.expr(expr);
+                let pattern = self.compile_pattern(pattern, Bind::Assign);
+                self.chunk.patterns.push(Rc::new(pattern));
+                self.emit(Op::Destructure(self.chunk.patterns.len() as u32 - 1));
+            }
+            Statement::Let {
+                pattern,
+                value,
+                constant,
+            } => {
+                self.expr(value);
+                if let Pattern::Binding(name) = pattern {
+                    let target = self.declare(name, *constant);
+                    self.emit(Op::Store(target));
+                } else {
+                    let bind = match constant {
+                        true => Bind::Constant,
+                        false => Bind::Declare,
+                    };
+                    let pattern = self.compile_pattern(pattern, bind);
+                    self.chunk.patterns.push(Rc::new(pattern));
+                    self.emit(Op::Bind(self.chunk.patterns.len() as u32 - 1));
+                }
+            }
+            Statement::Fn {
+                name, params, body, ..
+            } => {
+                let function = self.function(name, params, body);
+                self.chunk.functions.push(Rc::new(function));
+                self.emit(Op::Closure(self.chunk.functions.len() as u32 - 1));
+                let target = self.define(name);
+                self.emit(Op::Store(target));
+            }
+            Statement::Expr(expr) => {
+                self.expr(expr);
+                self.emit(Op::Pop);
+            }
+            Statement::Return(expr) => {
+                self.expr(expr);
+                match self.blocks {
+                    0 => self.emit(Op::Return),
+                    blocks => self.emit(Op::ReturnFromBlocks(blocks)),
+                };
+            }
+            Statement::If {
+                condition,
+                body,
+                else_body,
+            } => {
+                self.expr(condition);
+                let to_else = self.emit(Op::JumpIfFalse(0));
+                self.scoped_statements(body);
+                if else_body.is_empty() {
+                    self.patch(to_else);
+                } else {
+                    let to_end = self.emit(Op::Jump(0));
+                    self.patch(to_else);
+                    self.scoped_statements(else_body);
+                    self.patch(to_end);
+                }
+            }
+            Statement::For {
+                pattern,
+                iterable,
+                body,
+            } => {
+                self.expr(iterable);
+                self.emit(Op::Iter);
+                let start = self.here();
+                let next = self.emit(Op::Next(0));
+                // The scope of the names bound by the pattern is also the scope of the body
+                self.resolver.begin_scope();
+                let pattern = self.pattern(pattern);
+                self.emit(Op::Bind(pattern));
+                self.statements(body);
+                self.resolver.end_scope();
+                self.emit(Op::Jump(start));
+                self.patch(next);
+            }
+            Statement::Assert {
+                condition,
+                message,
+                line,
+                source,
+            } => {
+                let comparison = match condition {
+                    Expr::BinaryOp { op, lhs, rhs }
+                        if matches!(
+                            op,
+                            Token::Equal | Token::NotEqual | Token::LessThan | Token::GreaterThan
+                        ) =>
+                    {
+                        self.expr(lhs);
+                        self.expr(rhs);
+                        magic_method(op)
+                    }
+                    condition => {
+                        self.expr(condition);
+                        None
+                    }
+                };
+                let message = message
+                    .as_ref()
+                    .map(|message| Rc::new(self.expr_chunk(message)));
+                self.chunk.asserts.push(AssertCode {
+                    comparison,
+                    message,
+                    line: *line,
+                    source: source.clone(),
+                });
+                self.emit(Op::Assert(self.chunk.asserts.len() as u32 - 1));
+            }
+            // Macros are expanded by the parser
+            Statement::Macro { .. } => {}
+            Statement::Located {
+                statement: located, ..
+            } => {
+                self.locate(statement);
+                self.statement(located);
+            }
+            Statement::Defer(expr) => {
+                let block = self.expr_chunk(expr);
+                let block = self.block(block);
+                self.emit(Op::Defer(block));
+                self.chunk.has_cleanups = true;
+            }
+            Statement::With {
+                resource,
+                binding,
+                body,
+            } => {
+                self.expr(resource);
+                self.emit(Op::Enter);
+                self.chunk.has_cleanups = true;
+                self.resolver.begin_scope();
+                match binding {
+                    Some(pattern) => {
+                        let pattern = self.pattern(pattern);
+                        self.emit(Op::Bind(pattern));
+                    }
+                    None => {
+                        self.emit(Op::Pop);
+                    }
+                }
+                self.statements(body);
+                self.resolver.end_scope();
+                self.emit(Op::Exit);
+            }
+            Statement::Enum { name, variants } => {
+                let value = EnumInstance::new(name.clone(), variants.clone());
+                self.constant(Value::ClassInstance(Rc::new(value)));
+                let target = self.define(name);
+                self.emit(Op::Store(target));
+            }
+            Statement::Class {
+                name,
+                fields,
+                interfaces,
+                methods,
+                statics,
+            } => {
+                for interface in interfaces {
+                    self.load(interface);
+                }
+                for (_, value) in statics {
+                    self.expr(value);
+                }
+                let methods = methods
+                    .iter()
+                    .map(|method| {
+                        // The qualified name can't be written in a script,
+                        // so the method doesn't shadow a function of the same name in its body
+                        let qualified = format!("{}.{}", name, method.name);
+                        let function = self.function(&qualified, &method.params, &method.body);
+                        (method.kind, method.name.clone(), Rc::new(function))
+                    })
+                    .collect();
+                self.chunk.classes.push(Rc::new(ClassCode {
+                    name: name.clone(),
+                    fields: fields.clone(),
+                    interfaces: interfaces.clone(),
+                    methods,
+                    statics: statics.iter().map(|(field, _)| field.clone()).collect(),
+                }));
+                self.emit(Op::Class(self.chunk.classes.len() as u32 - 1));
+                let target = self.define(name);
+                self.emit(Op::Store(target));
+            }
+            Statement::SetField {
+                object,
+                field,
+                value,
+            } => {
+                self.expr(object);
+                self.expr(value);
+                self.emit(Op::SetMember(field.as_str().into()));
+            }
+            Statement::Interface { name, methods } => {
+                let value = InterfaceInstance::new(name.clone(), methods.clone());
+                self.constant(Value::ClassInstance(Rc::new(value)));
+                let target = self.define(name);
+                self.emit(Op::Store(target));
+            }
+            Statement::Match { subject, arms } => self.match_arms(subject, arms, false),
+        }
+    }
+
+    /// Compile a match, whose arms are statements or, for a match expression, blocks giving its value
+    fn match_arms(&mut self, subject: &Expr, arms: &[MatchArm], is_expr: bool) {
+        self.expr(subject);
+        let mut to_end = Vec::new();
+        for arm in arms {
+            self.resolver.begin_scope();
+            let pattern = self.pattern(&arm.pattern);
+            let to_next = self.emit(Op::MatchArm {
+                pattern,
+                otherwise: 0,
+            });
+            let to_guard_failure = arm.guard.as_ref().map(|guard| {
+                self.expr(guard);
+                self.emit(Op::JumpIfFalse(0))
+            });
+            self.emit(Op::Pop);
+            if is_expr {
+                let body = self.block_chunk(&arm.body);
+                let body = self.block(body);
+                self.emit(Op::Block(body));
+            } else {
+                self.statements(&arm.body);
+            }
+            self.resolver.end_scope();
+            to_end.push(self.emit(Op::Jump(0)));
+            if let Some(to_guard_failure) = to_guard_failure {
+                self.patch(to_guard_failure);
+            }
+            self.patch(to_next);
+        }
+        self.emit(Op::Pop);
+        if is_expr {
+            self.constant(Value::None);
+        }
+        for jump in to_end {
+            self.patch(jump);
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Number(n) => {
+                self.constant(Value::Number(*n));
+            }
+            Expr::Boolean(b) => self.constant(Value::Boolean(*b)),
+            Expr::String(s) => {
+                self.constant(Value::String(string::intern(s)));
+            }
+            Expr::List(items) => {
+                for item in items {
+                    self.expr(item);
+                }
+                self.emit(Op::List(items.len() as u32));
+            }
+            Expr::Tuple(items) => {
+                for item in items {
+                    self.expr(item);
+                }
+                self.emit(Op::Tuple(items.len() as u32));
+            }
+            Expr::Map(entries) => {
+                for (key, value) in entries {
+                    self.expr(key);
+                    self.expr(value);
+                }
+                self.emit(Op::Map(entries.len() as u32));
+            }
+            Expr::Block(statements) => {
+                let block = self.block_chunk(statements);
+                let block = self.block(block);
+                self.emit(Op::Block(block));
+            }
+            Expr::Is { value, class } => {
+                self.expr(value);
+                // A name that isn't a variable can still be the name of a builtin class
+                let bound = match self.resolver.lookup(class) {
+                    Some(variable) => {
+                        self.load_variable(variable, class);
+                        true
+                    }
+                    None => false,
+                };
+                self.emit(Op::Is {
+                    class: class.as_str().into(),
+                    bound,
+                });
+            }
+            Expr::ListComprehension {
+                element,
+                comprehension,
+            } => {
+                self.expr(&comprehension.iterable);
+                self.emit(Op::Iter);
+                self.emit(Op::List(0));
+                self.comprehension(comprehension, |compiler| {
+                    compiler.expr(element);
+                    compiler.emit(Op::ListPush);
+                });
+            }
+            Expr::MapComprehension {
+                key,
+                value,
+                comprehension,
+            } => {
+                self.expr(&comprehension.iterable);
+                self.emit(Op::Iter);
+                self.emit(Op::Map(0));
+                self.comprehension(comprehension, |compiler| {
+                    compiler.expr(key);
+                    compiler.expr(value);
+                    compiler.emit(Op::MapInsert);
+                });
+            }
+            Expr::Generator {
+                element,
+                comprehension,
+            } => {
+                self.expr(&comprehension.iterable);
+                // The elements are computed after the code creating the generator may have returned,
+                // so they are computed by a function of their own
+                self.resolver.begin_function();
+                let pattern = self.compile_pattern(&comprehension.pattern, Bind::Declare);
+                if let Some(condition) = &comprehension.condition {
+                    self.resolver.hoist_expr(condition);
+                }
+                self.resolver.hoist_expr(element);
+                let condition = comprehension
+                    .condition
+                    .as_ref()
+                    .map(|condition| Rc::new(self.expr_chunk(condition)));
+                let element = Rc::new(self.expr_chunk(element));
+                let (slots, captures) = self.resolver.end_function();
+                self.chunk.generators.push(Rc::new(GeneratorCode {
+                    pattern,
+                    condition,
+                    element,
+                    slots,
+                    captures,
+                }));
+                self.emit(Op::Generator(self.chunk.generators.len() as u32 - 1));
+            }
+            Expr::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                self.expr(start);
+                self.expr(end);
+                self.emit(Op::Range {
+                    inclusive: *inclusive,
+                });
+            }
+            Expr::Match { subject, arms } => self.match_arms(subject, arms, true),
+            Expr::If {
+                condition,
+                body,
+                else_body,
+            } => {
+                self.expr(condition);
+                let to_else = self.emit(Op::JumpIfFalse(0));
+                let body = self.block_chunk(body);
+                let body = self.block(body);
+                self.emit(Op::Block(body));
+                let to_end = self.emit(Op::Jump(0));
+                self.patch(to_else);
+                let else_body = self.block_chunk(else_body);
+                let else_body = self.block(else_body);
+                self.emit(Op::Block(else_body));
+                self.patch(to_end);
+            }
+            Expr::Identifier(name) => self.load(name),
+            Expr::BinaryOp { op, lhs, rhs } => {
+                self.expr(lhs);
+                let start = self.chunk.code.len();
+                self.expr(rhs);
+                match magic_method(op) {
+                    // A constant right side is read from the chunk by the operator, rather than pushed
+                    Some(method) => match self.chunk.code[start..] {
+                        [Op::Constant(constant)] => {
+                            self.chunk.code[start] = Op::BinaryConstant { method, constant };
+                        }
+                        _ => {
+                            self.emit(Op::Binary(method));
+                        }
+                    },
+                    None => self.fail(RuntimeError::new(
+                        ErrorKind::Syntax,
+                        format!("{:?} is not a binary operator", op),
+                    )),
+                }
+            }
+            Expr::Acessor(accessors) => {
+                let (origin, accessors) =
+                    accessors.split_first().expect("An accessor has an origin");
+                self.expr(origin);
+                for accessor in accessors {
+                    match accessor.unlocated() {
+                        Expr::Identifier(name) => {
+                            self.emit(Op::GetMember(name.as_str().into()));
+                        }
+                        Expr::Call(function, args) => {
+                            self.emit(Op::Pop);
+                            self.call(function, args);
+                        }
+                        _ => {
+                            self.emit(Op::InvalidAccessor);
+                        }
+                    }
+                }
+            }
+            Expr::Call(function, args) => self.call(function, args),
+            Expr::Located { pos, expr } => {
+                let outer = self.position;
+                self.mark(*pos);
+                self.expr(expr);
+                // The instructions after the call belong to the code around it
+                if let Some(outer) = outer {
+                    self.mark(outer);
+                }
+            }
+        }
+    }
+
+    fn call(&mut self, function: &Expr, args: &[Expr]) {
+        self.expr(function);
+        for arg in args {
+            self.expr(arg);
+        }
+        self.emit(Op::Call(args.len() as u32));
+    }
+
+    /// Compile the loop of a comprehension, whose iteration is started and whose result is on the stack.
+    /// `each` compiles the code adding an item to the result.
+    fn comprehension(&mut self, comprehension: &Comprehension, each: impl FnOnce(&mut Self)) {
+        let start = self.here();
+        let next = self.emit(Op::Next(0));
+        self.resolver.begin_scope();
+        let pattern = self.pattern(&comprehension.pattern);
+        self.emit(Op::Bind(pattern));
+        let to_skip = comprehension.condition.as_ref().map(|condition| {
+            self.expr(condition);
+            self.emit(Op::JumpIfFalse(0))
+        });
+        each(self);
+        if let Some(to_skip) = to_skip {
+            self.patch(to_skip);
+        }
+        self.resolver.end_scope();
+        self.emit(Op::Jump(start));
+        self.patch(next);
+    }
+
+    fn function(&mut self, name: &str, params: &[Pattern], body: &[Statement]) -> FunctionCode {
+        self.resolver.begin_function();
+        // The function is bound to its name in its own body, and the parameters can shadow it
+        self.declare(name, false);
+        let params: Vec<_> = params
+            .iter()
+            .map(|param| self.compile_pattern(param, Bind::Declare))
+            .collect();
+        let plain_params = params.iter().enumerate().all(|(index, param)| {
+            matches!(param.code, PatternCode::Binding(Target::Declare(slot)) if slot as usize == index + 1)
+        });
+        self.resolver.hoist(body);
+        let blocks = std::mem::take(&mut self.blocks);
+        let chunk = self.nested(|compiler| {
+            compiler.chunk.name = Some(name.into());
+            compiler.statements(body);
+        });
+        self.blocks = blocks;
+        let (slots, captures) = self.resolver.end_function();
+        FunctionCode {
+            name: name.into(),
+            params,
+            plain_params,
+            chunk: Rc::new(chunk),
+            slots,
+            captures,
+        }
+    }
+
+    /// Compile a block used as an expression: its value is the one of its last statement if it is an expression.
+    /// A `return` inside it returns from the function the block is in.
+    fn block_chunk(&mut self, statements: &[Statement]) -> Chunk {
+        self.resolver.begin_scope();
+        self.blocks += 1;
+        let chunk = self.nested(|compiler| {
+            let defers = statements
+                .iter()
+                .any(|statement| matches!(statement.unlocated(), Statement::Defer(_)));
+            if defers {
+                compiler.emit(Op::BeginCleanups);
+            }
+            for (i, statement) in statements.iter().enumerate() {
+                match statement.unlocated() {
+                    Statement::Expr(expr) if i == statements.len() - 1 => {
+                        compiler.locate(statement);
+                        compiler.expr(expr);
+                        compiler.emit(Op::Return);
+                    }
+                    _ => compiler.statement(statement),
+                }
+            }
+            if defers {
+                compiler.emit(Op::EndCleanups);
+            }
+        });
+        self.blocks -= 1;
+        self.resolver.end_scope();
+        chunk
+    }
+
+    /// Compile an expression evaluated apart from the main code, like a deferred expression
+    fn expr_chunk(&mut self, expr: &Expr) -> Chunk {
+        let blocks = std::mem::take(&mut self.blocks);
+        let chunk = self.nested(|compiler| {
+            compiler.expr(expr);
+            compiler.emit(Op::Return);
+        });
+        self.blocks = blocks;
+        chunk
+    }
+
+    /// Compile a pattern, whose names are bound as `bind` says
+    fn compile_pattern(&mut self, pattern: &Pattern, bind: Bind) -> CompiledPattern {
+        CompiledPattern {
+            source: pattern.clone(),
+            code: self.pattern_code(pattern, bind),
+        }
+    }
+
+    fn pattern_code(&mut self, pattern: &Pattern, bind: Bind) -> PatternCode {
+        let all = |compiler: &mut Self, patterns: &[Pattern]| {
+            patterns
+                .iter()
+                .map(|pattern| compiler.pattern_code(pattern, bind))
+                .collect()
+        };
+        let target = |compiler: &mut Self, name: &str| match bind {
+            Bind::Assign => compiler.assign(name),
+            Bind::Declare => compiler.declare(name, false),
+            Bind::Constant => compiler.declare(name, true),
+        };
+        match pattern {
+            Pattern::Wildcard => PatternCode::Wildcard,
+            Pattern::Literal(literal) => PatternCode::Literal(Rc::new(self.expr_chunk(literal))),
+            Pattern::Binding(name) => PatternCode::Binding(target(self, name)),
+            Pattern::Alternatives(alternatives) => {
+                PatternCode::Alternatives(all(self, alternatives))
+            }
+            Pattern::Class { name, fields } => PatternCode::Class {
+                name: name.clone(),
+                fields: all(self, fields),
+            },
+            Pattern::List(patterns) => PatternCode::List(all(self, patterns)),
+            Pattern::Tuple(patterns) => PatternCode::Tuple(all(self, patterns)),
+            Pattern::Rest(name) => {
+                PatternCode::Rest(name.as_deref().map(|name| target(self, name)))
+            }
+            // Type annotations are only checked by `frutta check`, not at runtime
+            Pattern::Typed { pattern, .. } => self.pattern_code(pattern, bind),
+        }
+    }
+}
+
+/// The magic method applying a binary operator, None if the token isn't one
+fn magic_method(op: &Token) -> Option<MagicMethod> {
+    let method = match op {
+        Token::Plus => MagicMethod::Add,
+        Token::Minus => MagicMethod::Sub,
+        Token::Star => MagicMethod::Mul,
+        Token::Divider => MagicMethod::Div,
+        Token::Modulo => MagicMethod::Mod,
+        Token::Equal => MagicMethod::Equal,
+        Token::NotEqual => MagicMethod::NotEqual,
+        Token::GreaterThan => MagicMethod::GreaterThan,
+        Token::LessThan => MagicMethod::LessThan,
+        _ => return None,
+    };
+    Some(method)
+}
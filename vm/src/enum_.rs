@@ -0,0 +1,202 @@
+use std::{cell::RefCell, rc::Rc};
+
+use parser::statement::EnumVariant;
+
+use crate::{
+    class::ClassInstance,
+    errors::{arity_error, unsupported, RuntimeError},
+    gc::Tracer,
+    list::ListInstance,
+    values_equal, BuiltinFunction, Function, MagicMethod, Value,
+};
+
+/// An enum declared in a script, like `enum Status { Pending, Done(result) }`.
+/// The enum is bound to its name, and its variants are accessed as fields: `Status.Pending`, `Status.Done(5)`.
+pub struct EnumInstance {
+    pub name: String,
+    pub variants: Rc<Vec<EnumVariant>>,
+}
+
+impl EnumInstance {
+    pub fn new(name: String, variants: Vec<EnumVariant>) -> Self {
+        EnumInstance {
+            name,
+            variants: Rc::new(variants),
+        }
+    }
+}
+
+impl std::fmt::Debug for EnumInstance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<enum {}>", self.name)
+    }
+}
+
+impl ClassInstance for EnumInstance {
+    fn class_name(&self) -> &str {
+        "Enum"
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    fn get_field(&self, name: &str) -> Option<Rc<RefCell<Value>>> {
+        if name == "variants" {
+            let names: Vec<_> = self
+                .variants
+                .iter()
+                .map(|variant| Rc::new(RefCell::new(Value::String(variant.name.as_str().into()))))
+                .collect();
+            return Some(Rc::new(RefCell::new(Value::Function(Function::Builtin(
+                BuiltinFunction::new(move |_args| {
+                    Ok(Value::ClassInstance(Rc::new(ListInstance::new(
+                        names.clone(),
+                    ))))
+                }),
+            )))));
+        }
+
+        let index = self.variants.iter().position(|v| v.name == name)?;
+        let value = if self.variants[index].fields.is_empty() {
+            Value::ClassInstance(Rc::new(VariantInstance::new(self, index, Vec::new())))
+        } else {
+            // Variants with a payload are constructed by calling them
+            let enum_name = self.name.clone();
+            let variants = Rc::clone(&self.variants);
+            Value::Function(Function::Builtin(BuiltinFunction::new(move |args| {
+                let variant = &variants[index];
+                if args.len() != variant.fields.len() {
+                    return Err(arity_error(
+                        &format!("{}.{}", enum_name, variant.name),
+                        variant.fields.len(),
+                        args.len(),
+                    ));
+                }
+                let owner = EnumInstance {
+                    name: enum_name.clone(),
+                    variants: Rc::clone(&variants),
+                };
+                Ok(Value::ClassInstance(Rc::new(VariantInstance::new(
+                    &owner, index, args,
+                ))))
+            })))
+        };
+        Some(Rc::new(RefCell::new(value)))
+    }
+
+    fn call_magic(
+        &self,
+        method: MagicMethod,
+        args: Vec<Rc<RefCell<Value>>>,
+    ) -> Result<Rc<RefCell<Value>>, RuntimeError> {
+        match method {
+            MagicMethod::Equal | MagicMethod::NotEqual => {
+                let rhs = args[1].borrow();
+                let same = rhs
+                    .as_any()
+                    .downcast_ref::<EnumInstance>()
+                    .is_some_and(|rhs| Rc::ptr_eq(&self.variants, &rhs.variants));
+                Ok(Rc::new(RefCell::new(Value::Boolean(
+                    same == (method == MagicMethod::Equal),
+                ))))
+            }
+            _ => unsupported(method, self.class_name(), &args),
+        }
+    }
+}
+
+/// A value of an enum, like `Status.Done(5)`.
+/// Its class name is qualified by the enum (`Status.Done`), and its payload can be read by field name.
+pub struct VariantInstance {
+    class_name: String,
+    variants: Rc<Vec<EnumVariant>>,
+    index: usize,
+    fields: Vec<Rc<RefCell<Value>>>,
+}
+
+impl VariantInstance {
+    fn new(owner: &EnumInstance, index: usize, fields: Vec<Rc<RefCell<Value>>>) -> Self {
+        VariantInstance {
+            class_name: format!("{}.{}", owner.name, owner.variants[index].name),
+            variants: Rc::clone(&owner.variants),
+            index,
+            fields,
+        }
+    }
+}
+
+impl std::fmt::Debug for VariantInstance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.fields.is_empty() {
+            return write!(f, "{}", self.class_name);
+        }
+        let fields: Vec<String> = self
+            .fields
+            .iter()
+            .map(|field| field.borrow().to_string())
+            .collect();
+        write!(f, "{}({})", self.class_name, fields.join(", "))
+    }
+}
+
+impl ClassInstance for VariantInstance {
+    fn class_name(&self) -> &str {
+        &self.class_name
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    fn trace(&self, tracer: &mut Tracer) {
+        for field in &self.fields {
+            tracer.visit(field);
+        }
+    }
+
+    fn destructure(&self) -> Option<Vec<Rc<RefCell<Value>>>> {
+        Some(self.fields.clone())
+    }
+
+    fn get_field(&self, name: &str) -> Option<Rc<RefCell<Value>>> {
+        if name == "variant" {
+            return Some(Rc::new(RefCell::new(Value::String(
+                self.variants[self.index].name.as_str().into(),
+            ))));
+        }
+        let position = self.variants[self.index]
+            .fields
+            .iter()
+            .position(|field| field == name)?;
+        Some(Rc::clone(&self.fields[position]))
+    }
+
+    fn call_magic(
+        &self,
+        method: MagicMethod,
+        args: Vec<Rc<RefCell<Value>>>,
+    ) -> Result<Rc<RefCell<Value>>, RuntimeError> {
+        match method {
+            MagicMethod::Equal | MagicMethod::NotEqual => {
+                let rhs = args[1].borrow();
+                let equal = rhs
+                    .as_any()
+                    .downcast_ref::<VariantInstance>()
+                    .is_some_and(|rhs| {
+                        Rc::ptr_eq(&self.variants, &rhs.variants)
+                            && self.index == rhs.index
+                            && self
+                                .fields
+                                .iter()
+                                .zip(rhs.fields.iter())
+                                .all(|(lhs, rhs)| values_equal(lhs, rhs))
+                    });
+                Ok(Rc::new(RefCell::new(Value::Boolean(
+                    equal == (method == MagicMethod::Equal),
+                ))))
+            }
+            _ => unsupported(method, self.class_name(), &args),
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use std::{cell::RefCell, fmt::Display, rc::Rc};
+
+use colored::Colorize;
+
+use crate::{MagicMethod, Value};
+
+/// The kinds of errors a program can fail with while running
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// A variable, class, field or interface that doesn't exist
+    Name,
+    /// A value of the wrong type, like calling a number or adding a list to a string
+    Type,
+    /// A value of the right type that can't be used, like an index out of range or an invalid date
+    Value,
+    /// A function or a class called with the wrong number of arguments
+    Arity,
+    /// Assigning or redeclaring a constant
+    Constant,
+    /// An `assert` whose condition is false
+    Assertion,
+    /// A file or the standard input that can't be read or written
+    Io,
+    /// A capability the VM wasn't given, like reading files
+    Permission,
+    /// A limit set on the VM exceeded, like its fuel or its call depth
+    Limit,
+    /// Source code that can't be parsed, given to `VM::eval`
+    Syntax,
+    /// A bug of the VM rather than of the program
+    Internal,
+}
+
+/// Where an error happened in the source of the program
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    /// The line, starting at 1
+    pub line: usize,
+    /// The character of the line, starting at 1
+    pub column: usize,
+    /// The source code of the line
+    pub source_line: String,
+}
+
+impl Span {
+    /// The span of the character at a position in a source
+    pub fn at(source: &str, pos: usize) -> Self {
+        let before = &source[..pos.min(source.len())];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+        Span {
+            line,
+            column: before[line_start..].chars().count() + 1,
+            source_line: source
+                .lines()
+                .nth(line.saturating_sub(1))
+                .unwrap_or_default()
+                .to_string(),
+        }
+    }
+}
+
+/// A function being run when an error happened
+#[derive(Debug, Clone, PartialEq)]
+pub struct StackFrame {
+    /// The name of the function, or `<program>` for the top level of the program
+    pub function: String,
+    /// The line the function was running, if the program was run with its source
+    pub line: Option<usize>,
+}
+
+/// An error stopping a program, returned by `VM::exec_program`
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeError {
+    pub kind: ErrorKind,
+    pub message: String,
+    /// Where the error happened, if the program was run with its source
+    pub span: Option<Span>,
+    /// The functions being run when the error happened, the innermost first
+    pub frames: Vec<StackFrame>,
+}
+
+impl RuntimeError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        RuntimeError {
+            kind,
+            message: message.into(),
+            span: None,
+            frames: Vec::new(),
+        }
+    }
+
+    /// The error of a source that can't be parsed, pointing at where the parser stopped
+    pub(crate) fn syntax(error: &parser::errors::Error, source: &str) -> Self {
+        // The parser errors are at the position after the character they point at
+        let before = &source[..error.pos().min(source.len())];
+        let pos = before.char_indices().next_back().map_or(0, |(pos, _)| pos);
+        RuntimeError {
+            span: Some(Span::at(source, pos)),
+            ..RuntimeError::new(ErrorKind::Syntax, error.message())
+        }
+    }
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let message = self.message.red().bold();
+        match &self.span {
+            Some(span) => {
+                write!(f, "{} at {}:{}", message, span.line, span.column)?;
+                let marker = "-".repeat(span.column.saturating_sub(1));
+                write!(
+                    f,
+                    "\n| {}\n| {}{}",
+                    span.source_line,
+                    marker.blue().bold(),
+                    "^".red().bold()
+                )?;
+            }
+            None => write!(f, "{}", message)?,
+        }
+        // The frames of a deep recursion are shown once, with the number of times they repeat
+        let mut frames = self.frames.iter().peekable();
+        while let Some(frame) = frames.next() {
+            match frame.line {
+                Some(line) => write!(f, "\n  in {} at line {}", frame.function, line)?,
+                None => write!(f, "\n  in {}", frame.function)?,
+            }
+            let mut repeated = 0;
+            while frames.next_if_eq(&frame).is_some() {
+                repeated += 1;
+            }
+            if repeated > 0 {
+                write!(f, "\n  ... repeated {} more times", repeated)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+/// Stop the running program with an error: `return raise(ErrorKind::Type, "...")`.
+/// The error goes back through the functions being run, which record where it happened on the way,
+/// and is returned by `VM::exec_program`.
+pub fn raise<T>(kind: ErrorKind, message: impl Into<String>) -> Result<T, RuntimeError> {
+    Err(RuntimeError::new(kind, message))
+}
+
+/// Stop the running program because an instance has no such magic method: either an operator
+/// can't be applied to the classes of its operands, or the instance can't be used by `with`
+pub(crate) fn unsupported<T>(
+    method: MagicMethod,
+    lhs: &str,
+    args: &[Rc<RefCell<Value>>],
+) -> Result<T, RuntimeError> {
+    match method {
+        MagicMethod::Enter | MagicMethod::Exit => not_a_resource(lhs),
+        _ => invalid_operands(method.operator(), lhs, args[1].borrow().class_name()),
+    }
+}
+
+/// Stop the running program because a value isn't a resource that a `with` statement can use
+pub(crate) fn not_a_resource<T>(class_name: &str) -> Result<T, RuntimeError> {
+    raise(
+        ErrorKind::Type,
+        format!("{} can't be used in a with statement", class_name),
+    )
+}
+
+/// Stop the running program because an operator can't be applied to the classes of its operands
+pub(crate) fn invalid_operands<T>(operator: &str, lhs: &str, rhs: &str) -> Result<T, RuntimeError> {
+    raise(
+        ErrorKind::Type,
+        format!("Cannot apply `{}` to {} and {}", operator, lhs, rhs),
+    )
+}
+
+/// The error of calling a function with a number of arguments it doesn't take
+pub(crate) fn arity_error(name: &str, expected: usize, got: usize) -> RuntimeError {
+    let plural = if expected == 1 { "" } else { "s" };
+    RuntimeError::new(
+        ErrorKind::Arity,
+        format!(
+            "{} expects {} argument{}, got {}",
+            name, expected, plural, got
+        ),
+    )
+}
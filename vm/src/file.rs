@@ -0,0 +1,185 @@
+use std::{
+    cell::RefCell,
+    fs::{File, OpenOptions},
+    io::{Read, Write},
+    path::Path,
+    rc::Rc,
+};
+
+use crate::{
+    class::ClassInstance,
+    errors::{raise, unsupported, ErrorKind, RuntimeError},
+    list::ListInstance,
+    permissions::VmPermissions,
+    BuiltinFunction, Function, MagicMethod, Value,
+};
+
+/// A file opened with `open(path)` (to read), `open(path, "w")` (to write) or `open(path, "a")` (to append).
+/// It is a resource: `with open(path) as f { ... }` closes it at the end of the block.
+pub struct FileInstance {
+    path: String,
+    file: Rc<RefCell<Option<File>>>,
+}
+
+impl FileInstance {
+    pub fn open(path: &str, mode: &str) -> Result<Self, RuntimeError> {
+        let file = match mode {
+            "r" => File::open(path),
+            "w" => File::create(path),
+            "a" => OpenOptions::new().append(true).create(true).open(path),
+            _ => raise(
+                ErrorKind::Value,
+                format!(
+                    "Unknown file mode '{}', expected \"r\", \"w\" or \"a\"",
+                    mode
+                ),
+            )?,
+        };
+        let file = file.or_else(|error| {
+            raise(
+                ErrorKind::Io,
+                format!("Failed to open '{}': {}", path, error),
+            )
+        })?;
+        Ok(FileInstance {
+            path: path.to_string(),
+            file: Rc::new(RefCell::new(Some(file))),
+        })
+    }
+
+    /// The builtin `open` function, opening the files the permissions allow
+    pub fn open_function(permissions: Rc<VmPermissions>) -> BuiltinFunction {
+        BuiltinFunction::new(move |args| {
+            let Some(path) = args.first().map(|path| path.borrow().to_string()) else {
+                return raise(ErrorKind::Arity, "open expects a path");
+            };
+            let mode = args
+                .get(1)
+                .map_or("r".to_string(), |mode| mode.borrow().to_string());
+            let (access, permission) = match mode.as_str() {
+                "r" => (&permissions.read, "read"),
+                _ => (&permissions.write, "write"),
+            };
+            if !access.allows(Path::new(&path)) {
+                return raise(
+                    ErrorKind::Permission,
+                    format!(
+                        "Permission denied: opening '{}' requires the '{}' permission",
+                        path, permission
+                    ),
+                );
+            }
+            Ok(Value::ClassInstance(Rc::new(FileInstance::open(
+                &path, &mode,
+            )?)))
+        })
+    }
+
+    fn read(file: &RefCell<Option<File>>, path: &str) -> Result<String, RuntimeError> {
+        let mut file = file.borrow_mut();
+        let file = FileInstance::opened(&mut file, path)?;
+        let mut content = String::new();
+        file.read_to_string(&mut content).or_else(|error| {
+            raise(
+                ErrorKind::Io,
+                format!("Failed to read '{}': {}", path, error),
+            )
+        })?;
+        Ok(content)
+    }
+
+    /// The file, unless it is closed
+    fn opened<'a>(file: &'a mut Option<File>, path: &str) -> Result<&'a mut File, RuntimeError> {
+        match file {
+            Some(file) => Ok(file),
+            None => raise(ErrorKind::Io, format!("File '{}' is closed", path)),
+        }
+    }
+
+    fn string(value: String) -> Rc<RefCell<Value>> {
+        Rc::new(RefCell::new(Value::String(value.into())))
+    }
+}
+
+impl std::fmt::Debug for FileInstance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<file {}>", self.path)
+    }
+}
+
+impl ClassInstance for FileInstance {
+    fn class_name(&self) -> &str {
+        "File"
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    fn get_field(&self, name: &str) -> Option<Rc<RefCell<Value>>> {
+        let file = Rc::clone(&self.file);
+        let path = self.path.clone();
+        let function = match name {
+            "read" => BuiltinFunction::new(move |_args| {
+                Ok(Value::String(FileInstance::read(&file, &path)?.into()))
+            }),
+            "lines" => BuiltinFunction::new(move |_args| {
+                let lines = FileInstance::read(&file, &path)?
+                    .lines()
+                    .map(|line| FileInstance::string(line.to_string()))
+                    .collect();
+                Ok(Value::ClassInstance(Rc::new(ListInstance::new(lines))))
+            }),
+            "write" => BuiltinFunction::new(move |args| {
+                let mut file = file.borrow_mut();
+                let file = FileInstance::opened(&mut file, &path)?;
+                for arg in args {
+                    write!(file, "{}", arg.borrow()).or_else(|error| {
+                        raise(
+                            ErrorKind::Io,
+                            format!("Failed to write '{}': {}", path, error),
+                        )
+                    })?;
+                }
+                Ok(Value::None)
+            }),
+            "close" => BuiltinFunction::new(move |_args| {
+                file.borrow_mut().take();
+                Ok(Value::None)
+            }),
+            "closed" => {
+                BuiltinFunction::new(move |_args| Ok(Value::Boolean(file.borrow().is_none())))
+            }
+            _ => return None,
+        };
+        Some(Rc::new(RefCell::new(Value::Function(Function::Builtin(
+            function,
+        )))))
+    }
+
+    fn call_magic(
+        &self,
+        method: MagicMethod,
+        args: Vec<Rc<RefCell<Value>>>,
+    ) -> Result<Rc<RefCell<Value>>, RuntimeError> {
+        match method {
+            // `with open(path) as f` binds the file itself
+            MagicMethod::Enter => Ok(Rc::clone(&args[0])),
+            MagicMethod::Exit => {
+                self.file.borrow_mut().take();
+                Ok(Rc::new(RefCell::new(Value::None)))
+            }
+            MagicMethod::Equal | MagicMethod::NotEqual => {
+                let same = args[1]
+                    .borrow()
+                    .as_any()
+                    .downcast_ref::<FileInstance>()
+                    .is_some_and(|rhs| Rc::ptr_eq(&self.file, &rhs.file));
+                Ok(Rc::new(RefCell::new(Value::Boolean(
+                    same == (method == MagicMethod::Equal),
+                ))))
+            }
+            _ => unsupported(method, self.class_name(), &args),
+        }
+    }
+}
@@ -0,0 +1,382 @@
+//! The collector of reference cycles.
+//!
+//! Values are reference counted, so they are freed as soon as nothing refers to them, except when they refer to
+//! each other: a list containing itself, two instances pointing at each other, or a function stored in a variable
+//! it captured. The containers that can be part of such cycles are tracked when they are created, and the
+//! collector finds the cycles by trial deletion: starting from the tracked containers, it counts the references
+//! each object gets from the other objects it reaches. An object with more references than that is referred to
+//! from outside, like the stack of the VM or a variable, so it is alive with everything it refers to. The other
+//! objects are only referred to by each other, and they are cleared to break their cycles, which frees them.
+//!
+//! References the collector can't see, like the ones held by builtin functions, only make objects look alive,
+//! so collecting is safe at any time.
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    rc::{Rc, Weak},
+};
+
+use crate::{class::Class, class::ClassInstance, Function, Value};
+
+/// The smallest number of tracked containers that starts a collection
+const MIN_THRESHOLD: usize = 1024;
+
+thread_local! {
+    static HEAP: RefCell<Heap> = RefCell::new(Heap::default());
+    /// Set when enough containers were created since the last collection, checked by the VM on calls
+    static PENDING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Something holding values, that can be part of a reference cycle
+pub trait Trace {
+    /// Visit the objects held
+    fn trace(&self, tracer: &mut Tracer);
+    /// Drop the values held, to break the cycles the object is part of.
+    /// Only called on objects nothing outside of their cycles refers to.
+    fn clear(&self) {}
+}
+
+/// The numbers reported by `Std.Gc.stats()`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// The number of tracked containers that are alive
+    pub tracked: usize,
+    /// The number of collections so far
+    pub collections: usize,
+    /// The number of objects freed by the collections so far
+    pub freed: usize,
+}
+
+struct Heap {
+    tracked: Vec<Box<dyn Tracked>>,
+    threshold: usize,
+    stats: Stats,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Heap {
+            tracked: Vec::new(),
+            threshold: MIN_THRESHOLD,
+            stats: Stats::default(),
+        }
+    }
+}
+
+impl Heap {
+    /// Forget the containers that were freed, the next check happening when the others doubled
+    fn prune(&mut self) {
+        self.tracked.retain(|tracked| tracked.is_alive());
+        self.threshold = MIN_THRESHOLD.max(self.tracked.len() * 2);
+    }
+}
+
+/// Track a container that can be part of a reference cycle, like the items of a list
+pub fn track<T: Trace + ?Sized + 'static>(object: &Rc<T>) {
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.tracked.push(Box::new(Rc::downgrade(object)));
+        if heap.tracked.len() >= heap.threshold {
+            // Most containers are freed as soon as they are unused, so many staying alive can mean cycles
+            let threshold = heap.threshold;
+            heap.prune();
+            if heap.tracked.len() > threshold / 2 {
+                PENDING.with(|pending| pending.set(true));
+            }
+        }
+    });
+}
+
+/// Collect if enough containers were created since the last collection
+pub fn collect_if_needed() {
+    if PENDING.with(|pending| pending.get()) {
+        collect();
+    }
+}
+
+/// Free the objects that are only referred to by cycles, returning how many were freed
+pub fn collect() -> usize {
+    PENDING.with(|pending| pending.set(false));
+    let roots: Vec<Rc<dyn Node>> = HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.tracked.retain(|tracked| tracked.is_alive());
+        heap.tracked
+            .iter()
+            .filter_map(|tracked| tracked.upgrade())
+            .collect()
+    });
+
+    let mut tracer = Tracer::default();
+    for root in roots {
+        tracer.add(root);
+    }
+    // Visiting the objects adds the ones they refer to, which are visited in turn
+    let mut index = 0;
+    while index < tracer.nodes.len() {
+        tracer.current = index;
+        let node = Rc::clone(&tracer.nodes[index].node);
+        node.trace(&mut tracer);
+        index += 1;
+    }
+
+    // The objects referred to from outside are alive, and so is everything they refer to
+    let mut alive: Vec<bool> = tracer
+        .nodes
+        .iter()
+        .map(|entry| {
+            // The collector holds one reference to each object
+            entry.node.strong_count() - 1 > entry.internal
+        })
+        .collect();
+    let mut pending: Vec<usize> = (0..alive.len()).filter(|&index| alive[index]).collect();
+    while let Some(index) = pending.pop() {
+        for &child in &tracer.nodes[index].children {
+            if !alive[child] {
+                alive[child] = true;
+                pending.push(child);
+            }
+        }
+    }
+
+    let garbage: Vec<Rc<dyn Node>> = tracer
+        .nodes
+        .into_iter()
+        .zip(alive)
+        .filter(|(_, alive)| !alive)
+        .map(|(entry, _)| entry.node)
+        .collect();
+    for node in &garbage {
+        node.clear();
+    }
+    let freed = garbage.len();
+    // Dropping the last references frees the objects
+    drop(garbage);
+
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.stats.collections += 1;
+        heap.stats.freed += freed;
+        heap.prune();
+    });
+    freed
+}
+
+/// Whether more than `max` tracked containers are alive, once their cycles are collected
+pub fn exceeds(max: usize) -> bool {
+    let tracked = HEAP.with(|heap| heap.borrow().tracked.len());
+    // The tracked containers include the ones freed since the last collection
+    if tracked <= max || stats().tracked <= max {
+        return false;
+    }
+    collect();
+    stats().tracked > max
+}
+
+/// The numbers of the collector, for `Std.Gc.stats()`
+pub fn stats() -> Stats {
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.tracked.retain(|tracked| tracked.is_alive());
+        Stats {
+            tracked: heap.tracked.len(),
+            ..heap.stats
+        }
+    })
+}
+
+/// Visits the objects referred to by an object, counting the references each object gets
+#[derive(Default)]
+pub struct Tracer {
+    ids: HashMap<*const (), usize>,
+    nodes: Vec<Entry>,
+    /// The object being visited
+    current: usize,
+}
+
+struct Entry {
+    node: Rc<dyn Node>,
+    /// The number of references from the visited objects
+    internal: usize,
+    children: Vec<usize>,
+}
+
+impl Tracer {
+    /// Record a reference from the object being visited
+    pub fn visit<T: Trace + ?Sized + 'static>(&mut self, object: &Rc<T>) {
+        let id = Rc::as_ptr(object) as *const ();
+        let index = match self.ids.get(&id) {
+            Some(&index) => index,
+            None => self.add(Rc::new(Rc::clone(object))),
+        };
+        self.nodes[index].internal += 1;
+        self.nodes[self.current].children.push(index);
+    }
+
+    /// Record the references of a value
+    pub fn value(&mut self, value: &Value) {
+        match value {
+            Value::ClassInstance(instance) => self.visit(instance),
+            Value::Class(class) => self.visit(class),
+            Value::Function(function) => self.function(function),
+            _ => {}
+        }
+    }
+
+    /// Record the references of a function: the variables it captured, and the receiver of a method
+    pub fn function(&mut self, function: &Function) {
+        match function {
+            Function::UserDefined { upvalues, .. } => self.visit(upvalues),
+            Function::Bound { receiver, function } => {
+                self.visit(receiver);
+                self.function(function);
+            }
+            Function::Builtin(_) => {}
+        }
+    }
+
+    fn add(&mut self, node: Rc<dyn Node>) -> usize {
+        let id = node.id();
+        if let Some(&index) = self.ids.get(&id) {
+            return index;
+        }
+        let index = self.nodes.len();
+        self.ids.insert(id, index);
+        self.nodes.push(Entry {
+            node,
+            internal: 0,
+            children: Vec::new(),
+        });
+        index
+    }
+}
+
+/// An object found by the collector, of any type
+trait Node {
+    fn id(&self) -> *const ();
+    fn strong_count(&self) -> usize;
+    fn trace(&self, tracer: &mut Tracer);
+    fn clear(&self);
+}
+
+impl<T: Trace + ?Sized> Node for Rc<T> {
+    fn id(&self) -> *const () {
+        Rc::as_ptr(self) as *const ()
+    }
+
+    fn strong_count(&self) -> usize {
+        Rc::strong_count(self)
+    }
+
+    fn trace(&self, tracer: &mut Tracer) {
+        T::trace(self, tracer)
+    }
+
+    fn clear(&self) {
+        T::clear(self)
+    }
+}
+
+/// A tracked container, which may have been freed
+trait Tracked {
+    fn is_alive(&self) -> bool;
+    fn upgrade(&self) -> Option<Rc<dyn Node>>;
+}
+
+impl<T: Trace + ?Sized + 'static> Tracked for Weak<T> {
+    fn is_alive(&self) -> bool {
+        self.strong_count() > 0
+    }
+
+    fn upgrade(&self) -> Option<Rc<dyn Node>> {
+        Weak::upgrade(self).map(|object| Rc::new(object) as Rc<dyn Node>)
+    }
+}
+
+/// A variable, or a field of an instance
+impl Trace for RefCell<Value> {
+    fn trace(&self, tracer: &mut Tracer) {
+        if let Ok(value) = self.try_borrow() {
+            tracer.value(&value);
+        }
+    }
+
+    fn clear(&self) {
+        if let Ok(mut value) = self.try_borrow_mut() {
+            *value = Value::None;
+        }
+    }
+}
+
+/// A variable captured by functions
+impl Trace for RefCell<Option<Value>> {
+    fn trace(&self, tracer: &mut Tracer) {
+        if let Ok(value) = self.try_borrow() {
+            if let Some(value) = &*value {
+                tracer.value(value);
+            }
+        }
+    }
+
+    fn clear(&self) {
+        if let Ok(mut value) = self.try_borrow_mut() {
+            *value = None;
+        }
+    }
+}
+
+/// The items of a list, or the fields of an instance
+impl Trace for RefCell<Vec<Rc<RefCell<Value>>>> {
+    fn trace(&self, tracer: &mut Tracer) {
+        if let Ok(items) = self.try_borrow() {
+            for item in items.iter() {
+                tracer.visit(item);
+            }
+        }
+    }
+
+    fn clear(&self) {
+        if let Ok(mut items) = self.try_borrow_mut() {
+            items.clear();
+        }
+    }
+}
+
+/// The entries of a map
+impl Trace for RefCell<Vec<(Rc<RefCell<Value>>, Rc<RefCell<Value>>)>> {
+    fn trace(&self, tracer: &mut Tracer) {
+        if let Ok(entries) = self.try_borrow() {
+            for (key, value) in entries.iter() {
+                tracer.visit(key);
+                tracer.visit(value);
+            }
+        }
+    }
+
+    fn clear(&self) {
+        if let Ok(mut entries) = self.try_borrow_mut() {
+            entries.clear();
+        }
+    }
+}
+
+/// The variables captured by a function
+impl Trace for [Rc<RefCell<Option<Value>>>] {
+    fn trace(&self, tracer: &mut Tracer) {
+        for upvalue in self {
+            tracer.visit(upvalue);
+        }
+    }
+}
+
+impl Trace for dyn ClassInstance {
+    fn trace(&self, tracer: &mut Tracer) {
+        ClassInstance::trace(self, tracer)
+    }
+}
+
+impl Trace for dyn Class {
+    fn trace(&self, tracer: &mut Tracer) {
+        Class::trace(self, tracer)
+    }
+}
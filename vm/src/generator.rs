@@ -0,0 +1,95 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    class::{ClassInstance, Items},
+    compiler::GeneratorCode,
+    errors::{unsupported, RuntimeError},
+    gc::Tracer,
+    MagicMethod, Upvalue, Value, VM,
+};
+
+/// A comprehension written in parentheses, like `(x * 2 for x in xs)`.
+/// The iterable is evaluated when the generator is created, but the elements are only computed while iterating,
+/// by a function capturing the variables visible where the generator was created.
+pub struct GeneratorInstance {
+    vm: VM,
+    upvalues: Rc<[Upvalue]>,
+    code: Rc<GeneratorCode>,
+    iterable: Value,
+}
+
+impl GeneratorInstance {
+    pub fn new(vm: VM, upvalues: Rc<[Upvalue]>, code: Rc<GeneratorCode>, iterable: Value) -> Self {
+        GeneratorInstance {
+            vm,
+            upvalues,
+            code,
+            iterable,
+        }
+    }
+}
+
+impl std::fmt::Debug for GeneratorInstance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<generator>")
+    }
+}
+
+impl ClassInstance for GeneratorInstance {
+    fn class_name(&self) -> &str {
+        "Generator"
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.visit(&self.upvalues);
+        tracer.value(&self.iterable);
+    }
+
+    fn iterate(&self) -> Option<Items> {
+        let mut vm = self.vm.capture();
+        let items = match vm.iterate(&self.iterable) {
+            Ok(items) => items,
+            // A value that isn't iterable fails the iteration over the generator
+            Err(error) => return Some(Box::new(std::iter::once(Err(error)))),
+        };
+        let upvalues = Rc::clone(&self.upvalues);
+        let code = Rc::clone(&self.code);
+        Some(Box::new(items.filter_map(move |item| {
+            let step = item.and_then(|item| {
+                let item = item.borrow().clone();
+                vm.generator_step(&code, &upvalues, &item)
+            });
+            step.map(|element| element.map(|element| Rc::new(RefCell::new(element))))
+                .transpose()
+        })))
+    }
+
+    fn get_field(&self, _name: &str) -> Option<Rc<RefCell<Value>>> {
+        None
+    }
+
+    fn call_magic(
+        &self,
+        method: MagicMethod,
+        args: Vec<Rc<RefCell<Value>>>,
+    ) -> Result<Rc<RefCell<Value>>, RuntimeError> {
+        match method {
+            // A generator is only equal to itself
+            MagicMethod::Equal | MagicMethod::NotEqual => {
+                let same = args[1]
+                    .borrow()
+                    .as_any()
+                    .downcast_ref::<GeneratorInstance>()
+                    .is_some_and(|rhs| std::ptr::eq(rhs, self));
+                Ok(Rc::new(RefCell::new(Value::Boolean(
+                    same == (method == MagicMethod::Equal),
+                ))))
+            }
+            _ => unsupported(method, self.class_name(), &args),
+        }
+    }
+}
@@ -0,0 +1,159 @@
+//! Limits on the resources programs use, to run programs that can't be trusted
+
+use std::{
+    cell::Cell,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    errors::{raise, ErrorKind, RuntimeError},
+    gc,
+};
+
+/// The default maximum call depth
+pub const DEFAULT_CALL_DEPTH: usize = 1000;
+
+/// The most runs of code nested on the Rust stack: the calls made by generators, deferred code,
+/// assertion messages and native functions, which start a new run while the VM is running.
+/// Each one takes up to 20 KB of stack in unoptimized builds, so they fit in the 2 MB of a spawned thread.
+const MAX_NESTED_RUNS: usize = 50;
+
+/// How often, in instructions, the limits other than the fuel are checked
+const CHECK_INTERVAL: u64 = 1024;
+
+/// The limits of a VM, set with `VM::set_limits`. A program exceeding one fails with an `ErrorKind::Limit` error.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Limits {
+    /// The number of instructions the VM can run, the programs it runs sharing them
+    pub fuel: Option<u64>,
+    /// The number of function calls that can be nested.
+    /// The script functions calling each other don't use the Rust stack, so any limit is safe for the host.
+    pub max_call_depth: Option<usize>,
+    /// The number of lists, maps, instances and captured variables that can be alive at once,
+    /// counting the ones of all the VMs of the thread
+    pub max_heap_objects: Option<usize>,
+    /// How long each program can run
+    pub timeout: Option<Duration>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            fuel: None,
+            max_call_depth: Some(DEFAULT_CALL_DEPTH),
+            max_heap_objects: None,
+            timeout: None,
+        }
+    }
+}
+
+/// What the programs of a VM used of its limits, shared with the generators they create
+#[derive(Debug)]
+pub(crate) struct Budget {
+    limits: Limits,
+    /// The instructions left, also counting down to the next check of the other limits
+    fuel: Cell<u64>,
+    depth: Cell<usize>,
+    /// The runs nested on the Rust stack
+    nested: Cell<usize>,
+    deadline: Cell<Option<Instant>>,
+}
+
+impl Budget {
+    pub fn new(limits: Limits) -> Self {
+        Budget {
+            fuel: Cell::new(limits.fuel.unwrap_or(u64::MAX)),
+            depth: Cell::new(0),
+            nested: Cell::new(0),
+            deadline: Cell::new(None),
+            limits,
+        }
+    }
+
+    /// Start the countdown to the deadline of a program
+    pub fn start(&self) {
+        let deadline = self.limits.timeout.map(|timeout| Instant::now() + timeout);
+        self.deadline.set(deadline);
+    }
+
+    /// Count an instruction about to run
+    #[inline]
+    pub fn tick(&self) -> Result<(), RuntimeError> {
+        let fuel = self.fuel.get();
+        if fuel.is_multiple_of(CHECK_INTERVAL) {
+            self.check(fuel)?;
+        }
+        self.fuel.set(fuel - 1);
+        Ok(())
+    }
+
+    #[cold]
+    fn check(&self, fuel: u64) -> Result<(), RuntimeError> {
+        if fuel == 0 {
+            return raise(
+                ErrorKind::Limit,
+                format!(
+                    "Out of fuel: the program ran {} instructions",
+                    self.limits.fuel.unwrap_or(u64::MAX)
+                ),
+            );
+        }
+        if let (Some(deadline), Some(timeout)) = (self.deadline.get(), self.limits.timeout) {
+            if Instant::now() > deadline {
+                return raise(
+                    ErrorKind::Limit,
+                    format!("Timeout: the program ran for more than {:?}", timeout),
+                );
+            }
+        }
+        if let Some(max) = self.limits.max_heap_objects {
+            if gc::exceeds(max) {
+                return raise(
+                    ErrorKind::Limit,
+                    format!("Heap limit exceeded: more than {} objects are alive", max),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Count a function call, until `exit` is called when it ends
+    pub fn enter(&self) -> Result<(), RuntimeError> {
+        let depth = self.depth.get() + 1;
+        if let Some(max) = self.limits.max_call_depth {
+            if depth > max {
+                return raise(
+                    ErrorKind::Limit,
+                    format!("Maximum call depth of {} exceeded", max),
+                );
+            }
+        }
+        self.depth.set(depth);
+        Ok(())
+    }
+
+    /// Stop counting a function call that ended, even if it failed
+    pub fn exit(&self) {
+        self.depth.set(self.depth.get() - 1);
+    }
+
+    /// Count a run of code nested on the Rust stack, until `unnest` is called when it ends
+    pub fn nest(&self) -> Result<(), RuntimeError> {
+        let nested = self.nested.get() + 1;
+        if nested > MAX_NESTED_RUNS {
+            return raise(
+                ErrorKind::Limit,
+                format!(
+                    "Maximum nesting of {} calls through generators, deferred code or native functions exceeded",
+                    MAX_NESTED_RUNS
+                ),
+            );
+        }
+        self.nested.set(nested);
+        Ok(())
+    }
+
+    pub fn unnest(&self) {
+        self.nested.set(self.nested.get() - 1);
+    }
+}
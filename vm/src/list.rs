@@ -0,0 +1,122 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    class::{Class, ClassInstance, Items},
+    errors::{raise, unsupported, ErrorKind, RuntimeError},
+    gc::{self, Tracer},
+    values_equal, BuiltinFunction, Function, MagicMethod, Value,
+};
+
+#[derive(Debug)]
+pub struct ListClass;
+
+impl Class for ListClass {
+    fn name(&self) -> &str {
+        "List"
+    }
+}
+
+/// A growable list of values, created with `[1, 2, 3]`.
+/// The items are shared, so methods like `push` are seen by every reference to the list.
+pub struct ListInstance {
+    pub items: Rc<RefCell<Vec<Rc<RefCell<Value>>>>>,
+}
+
+impl ListInstance {
+    pub fn new(items: Vec<Rc<RefCell<Value>>>) -> Self {
+        let items = Rc::new(RefCell::new(items));
+        gc::track(&items);
+        ListInstance { items }
+    }
+}
+
+impl std::fmt::Debug for ListInstance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let items: Vec<String> = self
+            .items
+            .borrow()
+            .iter()
+            .map(|item| item.borrow().to_string())
+            .collect();
+        write!(f, "[{}]", items.join(", "))
+    }
+}
+
+impl ClassInstance for ListInstance {
+    fn class_name(&self) -> &str {
+        "List"
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    /// Iterating over a list goes through the items it had when the loop started
+    fn iterate(&self) -> Option<Items> {
+        Some(Box::new(self.items.borrow().clone().into_iter().map(Ok)))
+    }
+
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.visit(&self.items);
+    }
+
+    fn get_field(&self, name: &str) -> Option<Rc<RefCell<Value>>> {
+        let items = Rc::clone(&self.items);
+        let function = match name {
+            "len" => {
+                BuiltinFunction::new(move |_args| Ok(Value::Number(items.borrow().len() as f64)))
+            }
+            "get" => BuiltinFunction::new(move |args| {
+                let Some(index) = args.first().and_then(|arg| arg.borrow().as_number()) else {
+                    return raise(ErrorKind::Type, "get expects a number");
+                };
+                let index = index as usize;
+                let Some(item) = items.borrow().get(index).cloned() else {
+                    return raise(ErrorKind::Value, format!("Index {} out of range", index));
+                };
+                let value = item.borrow().clone();
+                Ok(value)
+            }),
+            "push" => BuiltinFunction::new(move |args| {
+                items.borrow_mut().extend(args);
+                Ok(Value::None)
+            }),
+            _ => return None,
+        };
+        Some(Rc::new(RefCell::new(Value::Function(Function::Builtin(
+            function,
+        )))))
+    }
+
+    fn call_magic(
+        &self,
+        method: MagicMethod,
+        args: Vec<Rc<RefCell<Value>>>,
+    ) -> Result<Rc<RefCell<Value>>, RuntimeError> {
+        let rhs = args.get(1).map(|rhs| rhs.borrow());
+        let Some(rhs) = rhs
+            .as_ref()
+            .and_then(|rhs| rhs.as_any().downcast_ref::<ListInstance>())
+        else {
+            return unsupported(method, self.class_name(), &args);
+        };
+        let lhs_items = self.items.borrow();
+        let rhs_items = rhs.items.borrow();
+
+        let result = match method {
+            MagicMethod::Add => Value::ClassInstance(Rc::new(ListInstance::new(
+                lhs_items.iter().chain(rhs_items.iter()).cloned().collect(),
+            ))),
+            MagicMethod::Equal | MagicMethod::NotEqual => {
+                let equal = lhs_items.len() == rhs_items.len()
+                    && lhs_items
+                        .iter()
+                        .zip(rhs_items.iter())
+                        .all(|(lhs, rhs)| values_equal(lhs, rhs));
+                Value::Boolean(equal == (method == MagicMethod::Equal))
+            }
+            _ => unsupported(method, self.class_name(), &args)?,
+        };
+        Ok(Rc::new(RefCell::new(result)))
+    }
+}
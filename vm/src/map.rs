@@ -0,0 +1,177 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    class::{Class, ClassInstance, Items},
+    errors::{arity_error, unsupported, RuntimeError},
+    gc::{self, Tracer},
+    list::ListInstance,
+    tuple::TupleInstance,
+    values_equal, BuiltinFunction, Function, MagicMethod, Value,
+};
+
+type Entry = (Rc<RefCell<Value>>, Rc<RefCell<Value>>);
+type Entries = Rc<RefCell<Vec<Entry>>>;
+
+#[derive(Debug)]
+pub struct MapClass;
+
+impl Class for MapClass {
+    fn name(&self) -> &str {
+        "Map"
+    }
+}
+
+/// A map from keys to values, created with `{"a": 1, "b": 2}`.
+/// The entries keep their insertion order, and like lists they are shared between the references to the map.
+pub struct MapInstance {
+    pub entries: Entries,
+}
+
+impl MapInstance {
+    pub fn new(entries: Vec<Entry>) -> Self {
+        let map = MapInstance {
+            entries: Rc::new(RefCell::new(Vec::new())),
+        };
+        gc::track(&map.entries);
+        for (key, value) in entries {
+            map.insert(key, value);
+        }
+        map
+    }
+
+    /// Set the value of a key, replacing the previous one if the key is already in the map
+    pub fn insert(&self, key: Rc<RefCell<Value>>, value: Rc<RefCell<Value>>) {
+        MapInstance::insert_entry(&self.entries, key, value);
+    }
+
+    fn insert_entry(entries: &Entries, key: Rc<RefCell<Value>>, value: Rc<RefCell<Value>>) {
+        let mut entries = entries.borrow_mut();
+        match entries.iter_mut().find(|(k, _)| values_equal(k, &key)) {
+            Some(entry) => entry.1 = value,
+            None => entries.push((key, value)),
+        }
+    }
+
+    /// The key a method like `get` is called with
+    fn key<'a>(
+        args: &'a [Rc<RefCell<Value>>],
+        method: &str,
+    ) -> Result<&'a Rc<RefCell<Value>>, RuntimeError> {
+        match args {
+            [key] => Ok(key),
+            _ => Err(arity_error(method, 1, args.len())),
+        }
+    }
+
+    fn find(entries: &Entries, key: &Rc<RefCell<Value>>) -> Option<Rc<RefCell<Value>>> {
+        entries
+            .borrow()
+            .iter()
+            .find(|(k, _)| values_equal(k, key))
+            .map(|(_, value)| Rc::clone(value))
+    }
+}
+
+impl std::fmt::Debug for MapInstance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let entries: Vec<String> = self
+            .entries
+            .borrow()
+            .iter()
+            .map(|(key, value)| format!("{}: {}", key.borrow(), value.borrow()))
+            .collect();
+        write!(f, "{{{}}}", entries.join(", "))
+    }
+}
+
+impl ClassInstance for MapInstance {
+    fn class_name(&self) -> &str {
+        "Map"
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.visit(&self.entries);
+    }
+
+    /// Iterating over a map goes through `(key, value)` tuples, so `for k, v in map` works
+    fn iterate(&self) -> Option<Items> {
+        let entries = self.entries.borrow().clone();
+        Some(Box::new(entries.into_iter().map(|(key, value)| {
+            Ok(Rc::new(RefCell::new(Value::ClassInstance(Rc::new(
+                TupleInstance {
+                    items: vec![key, value],
+                },
+            )))))
+        })))
+    }
+
+    fn get_field(&self, name: &str) -> Option<Rc<RefCell<Value>>> {
+        let entries = Rc::clone(&self.entries);
+        let function = match name {
+            "len" => {
+                BuiltinFunction::new(move |_args| Ok(Value::Number(entries.borrow().len() as f64)))
+            }
+            "get" => BuiltinFunction::new(move |args| {
+                let key = MapInstance::key(&args, "get")?;
+                Ok(MapInstance::find(&entries, key)
+                    .map(|value| value.borrow().clone())
+                    .unwrap_or(Value::None))
+            }),
+            "set" => BuiltinFunction::new(move |args| {
+                let [key, value] = args.as_slice() else {
+                    return Err(arity_error("set", 2, args.len()));
+                };
+                MapInstance::insert_entry(&entries, Rc::clone(key), Rc::clone(value));
+                Ok(Value::None)
+            }),
+            "contains" => BuiltinFunction::new(move |args| {
+                let key = MapInstance::key(&args, "contains")?;
+                Ok(Value::Boolean(MapInstance::find(&entries, key).is_some()))
+            }),
+            "keys" => BuiltinFunction::new(move |_args| {
+                let keys = entries.borrow().iter().map(|(k, _)| Rc::clone(k)).collect();
+                Ok(Value::ClassInstance(Rc::new(ListInstance::new(keys))))
+            }),
+            "values" => BuiltinFunction::new(move |_args| {
+                let values = entries.borrow().iter().map(|(_, v)| Rc::clone(v)).collect();
+                Ok(Value::ClassInstance(Rc::new(ListInstance::new(values))))
+            }),
+            _ => return None,
+        };
+        Some(Rc::new(RefCell::new(Value::Function(Function::Builtin(
+            function,
+        )))))
+    }
+
+    fn call_magic(
+        &self,
+        method: MagicMethod,
+        args: Vec<Rc<RefCell<Value>>>,
+    ) -> Result<Rc<RefCell<Value>>, RuntimeError> {
+        let rhs = args.get(1).map(|rhs| rhs.borrow());
+        let Some(rhs) = rhs
+            .as_ref()
+            .and_then(|rhs| rhs.as_any().downcast_ref::<MapInstance>())
+        else {
+            return unsupported(method, self.class_name(), &args);
+        };
+
+        let result = match method {
+            MagicMethod::Equal | MagicMethod::NotEqual => {
+                let lhs_entries = self.entries.borrow();
+                let equal = lhs_entries.len() == rhs.entries.borrow().len()
+                    && lhs_entries.iter().all(|(key, value)| {
+                        MapInstance::find(&rhs.entries, key)
+                            .is_some_and(|other| values_equal(value, &other))
+                    });
+                Value::Boolean(equal == (method == MagicMethod::Equal))
+            }
+            _ => unsupported(method, self.class_name(), &args)?,
+        };
+        Ok(Rc::new(RefCell::new(result)))
+    }
+}
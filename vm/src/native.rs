@@ -0,0 +1,452 @@
+//! The API to extend the VM with Rust functions and classes, like `vm.register_fn("add", |a: f64, b: f64| ...)`,
+//! and to use the values of the scripts from Rust, like `vm.call::<f64>("area", (2.0, 3.0))`.
+//! The values going between the scripts and Rust are converted by the `FromValue` and `IntoValue` traits,
+//! which report the values of the wrong type as errors of the script.
+
+use std::{cell::RefCell, marker::PhantomData, rc::Rc};
+
+use crate::{
+    class::{Class, ClassInstance},
+    errors::{arity_error, ErrorKind, RuntimeError},
+    list::ListInstance,
+    BuiltinFunction, Function, Value,
+};
+
+/// A Rust value that can be read from a value of the scripts, like the arguments of a native function
+pub trait FromValue: Sized {
+    fn from_value(value: &Value) -> Result<Self, RuntimeError>;
+}
+
+/// A Rust value that can be given to the scripts, like the result of a native function
+pub trait IntoValue {
+    fn into_value(self) -> Value;
+}
+
+/// The error of a value of the wrong type
+pub fn type_mismatch(expected: &str, value: &Value) -> RuntimeError {
+    RuntimeError::new(
+        ErrorKind::Type,
+        format!("expected {}, got {}", expected, value.class_name()),
+    )
+}
+
+impl FromValue for Value {
+    fn from_value(value: &Value) -> Result<Self, RuntimeError> {
+        Ok(value.clone())
+    }
+}
+
+/// Any value, ignored, like the result of a function called for its effects
+impl FromValue for () {
+    fn from_value(_value: &Value) -> Result<Self, RuntimeError> {
+        Ok(())
+    }
+}
+
+impl FromValue for f64 {
+    fn from_value(value: &Value) -> Result<Self, RuntimeError> {
+        value
+            .as_number()
+            .ok_or_else(|| type_mismatch("a Number", value))
+    }
+}
+
+impl FromValue for i64 {
+    fn from_value(value: &Value) -> Result<Self, RuntimeError> {
+        match value.as_number() {
+            Some(n) if n.fract() == 0.0 => Ok(n as i64),
+            _ => Err(type_mismatch("an integer", value)),
+        }
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: &Value) -> Result<Self, RuntimeError> {
+        match value {
+            Value::Boolean(b) => Ok(*b),
+            _ => Err(type_mismatch("a Boolean", value)),
+        }
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: &Value) -> Result<Self, RuntimeError> {
+        value
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| type_mismatch("a String", value))
+    }
+}
+
+/// None, or a value of the type
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: &Value) -> Result<Self, RuntimeError> {
+        match value {
+            Value::None => Ok(None),
+            value => T::from_value(value).map(Some),
+        }
+    }
+}
+
+/// A list whose items all have the type
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: &Value) -> Result<Self, RuntimeError> {
+        let list = value
+            .as_any()
+            .downcast_ref::<ListInstance>()
+            .ok_or_else(|| type_mismatch("a List", value))?;
+        let items = list.items.borrow();
+        items
+            .iter()
+            .map(|item| T::from_value(&item.borrow()))
+            .collect()
+    }
+}
+
+/// An instance of a native class, copied out of the script
+impl<T: NativeClass> FromValue for T {
+    fn from_value(value: &Value) -> Result<Self, RuntimeError> {
+        match value {
+            Value::ClassInstance(instance) => T::from_instance(&**instance),
+            _ => None,
+        }
+        .ok_or_else(|| type_mismatch(&format!("a {}", T::NAME), value))
+    }
+}
+
+impl IntoValue for Value {
+    fn into_value(self) -> Value {
+        self
+    }
+}
+
+impl IntoValue for () {
+    fn into_value(self) -> Value {
+        Value::None
+    }
+}
+
+impl IntoValue for f64 {
+    fn into_value(self) -> Value {
+        Value::Number(self)
+    }
+}
+
+impl IntoValue for i64 {
+    fn into_value(self) -> Value {
+        Value::Number(self as f64)
+    }
+}
+
+impl IntoValue for bool {
+    fn into_value(self) -> Value {
+        Value::Boolean(self)
+    }
+}
+
+impl IntoValue for String {
+    fn into_value(self) -> Value {
+        Value::String(self.into())
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value(self) -> Value {
+        Value::String(self.into())
+    }
+}
+
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value(self) -> Value {
+        self.map_or(Value::None, T::into_value)
+    }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self) -> Value {
+        let items = self
+            .into_iter()
+            .map(|item| Rc::new(RefCell::new(item.into_value())))
+            .collect();
+        Value::ClassInstance(Rc::new(ListInstance::new(items)))
+    }
+}
+
+impl<T: NativeClass> IntoValue for T {
+    fn into_value(self) -> Value {
+        Value::ClassInstance(self.into_instance())
+    }
+}
+
+impl From<String> for RuntimeError {
+    /// The error of a native function failing with a message
+    fn from(message: String) -> Self {
+        RuntimeError::new(ErrorKind::Value, message)
+    }
+}
+
+impl From<&str> for RuntimeError {
+    fn from(message: &str) -> Self {
+        RuntimeError::new(ErrorKind::Value, message)
+    }
+}
+
+/// A Rust closure that can be called by the scripts, its arguments being converted from the values it is called with.
+/// It is implemented for the closures taking up to 6 arguments and returning a `Result`.
+pub trait NativeFunction<Args>: 'static {
+    /// The number of arguments the function takes
+    fn arity(&self) -> usize;
+    fn call(&self, args: &[Value]) -> Result<Value, RuntimeError>;
+}
+
+/// Fail if a native function called `name` isn't given `arity` arguments
+pub fn check_arity(name: &str, args: &[Value], arity: usize) -> Result<(), RuntimeError> {
+    if args.len() == arity {
+        return Ok(());
+    }
+    Err(arity_error(name, arity, args.len()))
+}
+
+/// Convert the argument at `index` of a native function
+pub fn argument<T: FromValue>(args: &[Value], index: usize) -> Result<T, RuntimeError> {
+    T::from_value(&args[index]).map_err(|error| RuntimeError {
+        message: format!("argument {}: {}", index + 1, error.message),
+        ..error
+    })
+}
+
+macro_rules! impl_native_function {
+    ($($arg:ident),*) => {
+        impl<F, R, E, $($arg,)*> NativeFunction<($($arg,)*)> for F
+        where
+            F: Fn($($arg),*) -> Result<R, E> + 'static,
+            R: IntoValue,
+            E: Into<RuntimeError>,
+            $($arg: FromValue,)*
+        {
+            fn arity(&self) -> usize {
+                <[&str]>::len(&[$(stringify!($arg)),*])
+            }
+
+            #[allow(non_snake_case, unused_variables, unused_mut, unused_assignments)]
+            fn call(&self, args: &[Value]) -> Result<Value, RuntimeError> {
+                let mut index = 0;
+                $(
+                    let $arg = argument::<$arg>(args, index)?;
+                    index += 1;
+                )*
+                self($($arg),*).map(R::into_value).map_err(Into::into)
+            }
+        }
+    };
+}
+
+impl_native_function!();
+impl_native_function!(A);
+impl_native_function!(A, B);
+impl_native_function!(A, B, C);
+impl_native_function!(A, B, C, D);
+impl_native_function!(A, B, C, D, G);
+impl_native_function!(A, B, C, D, G, H);
+
+/// The arguments given to a function of the scripts by `VM::call`, like `(2.0, "text")`.
+/// It is implemented for the tuples of up to 6 values implementing `IntoValue`, and for `Vec<Value>`.
+pub trait IntoArgs {
+    fn into_args(self) -> Vec<Value>;
+}
+
+impl IntoArgs for Vec<Value> {
+    fn into_args(self) -> Vec<Value> {
+        self
+    }
+}
+
+macro_rules! impl_into_args {
+    ($($arg:ident),*) => {
+        impl<$($arg: IntoValue,)*> IntoArgs for ($($arg,)*) {
+            #[allow(non_snake_case)]
+            fn into_args(self) -> Vec<Value> {
+                let ($($arg,)*) = self;
+                vec![$($arg.into_value()),*]
+            }
+        }
+    };
+}
+
+impl_into_args!();
+impl_into_args!(A);
+impl_into_args!(A, B);
+impl_into_args!(A, B, C);
+impl_into_args!(A, B, C, D);
+impl_into_args!(A, B, C, D, G);
+impl_into_args!(A, B, C, D, G, H);
+
+/// Wrap a native function in a builtin function of the VM, named `name` in the errors it reports
+pub(crate) fn builtin<Args, F: NativeFunction<Args>>(name: &str, function: F) -> BuiltinFunction {
+    let name = name.to_string();
+    BuiltinFunction::new(move |args| {
+        let args: Vec<Value> = args.iter().map(|arg| arg.borrow().clone()).collect();
+        check_arity(&name, &args, function.arity())?;
+        function.call(&args).map_err(|error| RuntimeError {
+            message: format!("{}: {}", name, error.message),
+            ..error
+        })
+    })
+}
+
+/// A native function as a value of the scripts, like the methods of the classes derived with `#[frutta_methods]`
+pub fn function<Args, F: NativeFunction<Args>>(name: &str, function: F) -> Value {
+    Value::Function(Function::Builtin(builtin(name, function)))
+}
+
+/// A Rust type exposed to the scripts as a class, registered with `VM::register_class`.
+/// It is either implemented by hand for a type implementing `ClassInstance`, or derived with `#[derive(FruttaClass)]`.
+pub trait NativeClass: Sized + 'static {
+    /// The name of the class in the scripts
+    const NAME: &'static str;
+
+    /// Create an instance from the arguments the class is called with, like `Point(1, 2)`
+    fn construct(_args: &[Value]) -> Result<Self, RuntimeError> {
+        Err(RuntimeError::new(
+            ErrorKind::Type,
+            format!("Class '{}' cannot be called", Self::NAME),
+        ))
+    }
+
+    /// A static member of the class, like `Point.ORIGIN`
+    fn get_static(_name: &str) -> Option<Value> {
+        None
+    }
+
+    /// Give a value to the scripts, usually as `Rc::new(self)`
+    fn into_instance(self) -> Rc<dyn ClassInstance>;
+
+    /// Copy a value out of the scripts, None if the instance is of another class
+    fn from_instance(instance: &dyn ClassInstance) -> Option<Self>;
+}
+
+/// The class of a native type, bound to its name in the scripts
+pub(crate) struct NativeClassOf<T>(PhantomData<T>);
+
+impl<T> NativeClassOf<T> {
+    pub fn new() -> Self {
+        NativeClassOf(PhantomData)
+    }
+}
+
+impl<T: NativeClass> std::fmt::Debug for NativeClassOf<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<class {}>", T::NAME)
+    }
+}
+
+impl<T: NativeClass> Class for NativeClassOf<T> {
+    fn name(&self) -> &str {
+        T::NAME
+    }
+
+    fn get_static(&self, name: &str) -> Option<Rc<RefCell<Value>>> {
+        T::get_static(name).map(|value| Rc::new(RefCell::new(value)))
+    }
+
+    fn construct(&self, args: Vec<Rc<RefCell<Value>>>) -> Result<Value, RuntimeError> {
+        let args: Vec<Value> = args.iter().map(|arg| arg.borrow().clone()).collect();
+        let instance = T::construct(&args)?;
+        Ok(Value::ClassInstance(instance.into_instance()))
+    }
+}
+
+/// The fields of a struct exposed to the scripts, implemented by `#[derive(FruttaClass)]`
+pub trait FruttaClass: NativeClass + FruttaMethods {
+    /// The value of a field, None if the scripts can't read it
+    fn get(&self, name: &str) -> Option<Value>;
+    /// Assign a field, None if the scripts can't assign it
+    fn set(&mut self, name: &str, value: &Value) -> Option<Result<(), RuntimeError>>;
+    /// The values of the fields the scripts can read, in order
+    fn fields(&self) -> Vec<Value>;
+}
+
+/// The methods of a struct exposed to the scripts, implemented by `#[frutta_methods]` on one of its `impl` blocks.
+/// `#[derive(FruttaClass)]` implements it without methods, unless the struct has the `#[frutta(methods)]` attribute.
+pub trait FruttaMethods: Sized + 'static {
+    /// A method of an instance, bound to it
+    fn method(_instance: &Rc<RefCell<Self>>, _name: &str) -> Option<Value> {
+        None
+    }
+
+    /// An associated function, called on the class like `Point.origin()`
+    fn function(_name: &str) -> Option<Value> {
+        None
+    }
+}
+
+/// An instance of a class derived with `#[derive(FruttaClass)]`. The scripts can assign its fields and call its
+/// `&mut self` methods, so the value is shared by all the references to the instance.
+pub struct Instance<T>(Rc<RefCell<T>>);
+
+impl<T: FruttaClass> Instance<T> {
+    /// Give a value to the scripts as an instance of its class
+    pub fn wrap(value: T) -> Rc<dyn ClassInstance> {
+        Rc::new(Instance(Rc::new(RefCell::new(value))))
+    }
+
+    /// A copy of the value of an instance, None if it is of another class
+    pub fn get(instance: &dyn ClassInstance) -> Option<T>
+    where
+        T: Clone,
+    {
+        let instance = instance.as_any().downcast_ref::<Self>()?;
+        Some(instance.0.borrow().clone())
+    }
+}
+
+impl<T: FruttaClass> std::fmt::Debug for Instance<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let fields: Vec<String> = self
+            .0
+            .borrow()
+            .fields()
+            .iter()
+            .map(Value::to_string)
+            .collect();
+        write!(f, "{}({})", T::NAME, fields.join(", "))
+    }
+}
+
+impl<T: FruttaClass> ClassInstance for Instance<T> {
+    fn class_name(&self) -> &str {
+        T::NAME
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    fn get_field(&self, name: &str) -> Option<Rc<RefCell<Value>>> {
+        let value = self.0.borrow().get(name);
+        value
+            .or_else(|| T::method(&self.0, name))
+            .map(|value| Rc::new(RefCell::new(value)))
+    }
+
+    fn set_field(&self, name: &str, value: Rc<RefCell<Value>>) -> Result<bool, RuntimeError> {
+        match self.0.borrow_mut().set(name, &value.borrow()) {
+            Some(Ok(())) => Ok(true),
+            Some(Err(error)) => Err(RuntimeError {
+                message: format!("field '{}': {}", name, error.message),
+                ..error
+            }),
+            None => Ok(false),
+        }
+    }
+
+    fn destructure(&self) -> Option<Vec<Rc<RefCell<Value>>>> {
+        let fields = self.0.borrow().fields();
+        Some(
+            fields
+                .into_iter()
+                .map(|field| Rc::new(RefCell::new(field)))
+                .collect(),
+        )
+    }
+}
@@ -1,96 +1,78 @@
 use std::{cell::RefCell, rc::Rc};
 
 use crate::{
-    class::{Class, ClassInstance},
-    MagicMethod, Value,
+    class::Class,
+    errors::{raise, ErrorKind, RuntimeError},
+    BuiltinFunction, Function, Value,
 };
 
 #[derive(Debug)]
 pub struct NumberClass;
 
 impl Class for NumberClass {
-    fn create_instance(&self) -> Rc<dyn ClassInstance> {
-        Rc::new(NumberInstance { value: 0.0 })
+    fn name(&self) -> &str {
+        "Number"
     }
-}
-
-pub struct NumberInstance {
-    pub value: f64,
-}
-
-impl NumberInstance {
-    pub fn new(value: f64) -> Self {
-        NumberInstance { value }
-    }
-}
 
-impl std::fmt::Debug for NumberInstance {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:}", self.value)
-    }
-}
-
-impl ClassInstance for NumberInstance {
-    fn as_any(&self) -> &dyn std::any::Any {
-        self
-    }
-
-    fn get_field(&self, name: &str) -> Option<Rc<RefCell<Value>>> {
-        if name == "value" {
-            Some(Rc::new(RefCell::new(Value::ClassInstance(Rc::new(
-                NumberInstance { value: self.value },
-            )))))
-        } else {
-            match name {
-                "abs" => {
-                    let value = self.value;
-                    Some(Rc::new(RefCell::new(Value::Function(crate::Function::Builtin(crate::BuiltinFunction::new(
-                        move |_args| {
-                            return Value::ClassInstance(Rc::new(NumberInstance::new(value.abs())));
-                        }
-                    ))))))
-                },
-                _ => None,
-            }
-        }
+    fn get_static(&self, name: &str) -> Option<Rc<RefCell<Value>>> {
+        let value = match name {
+            "MAX" => Value::Number(f64::MAX),
+            "MIN" => Value::Number(f64::MIN),
+            // `Number.parse("3.2")` reads a number from a string
+            "parse" => Value::Function(Function::Builtin(BuiltinFunction::new(|args| {
+                let text = match args.first().map(|arg| arg.borrow().clone()) {
+                    Some(Value::String(text)) => text,
+                    _ => raise(ErrorKind::Type, "Number.parse expects a string")?,
+                };
+                Ok(Value::Number(NumberClass::parse(&text)?))
+            }))),
+            _ => return None,
+        };
+        Some(Rc::new(RefCell::new(value)))
     }
 
-    fn call_magic(&self, method: MagicMethod, args: Vec<Rc<RefCell<Value>>>) -> Rc<RefCell<Value>> {
-        let rhs = if let Value::ClassInstance(instance) = &*args[1].borrow() {
-            if let Some(Value::ClassInstance(rhs_instance)) =
-                instance.get_field("value").map(|v| v.borrow().clone())
-            {
-                if let Some(rhs_instance) = rhs_instance.as_any().downcast_ref::<NumberInstance>() {
-                    rhs_instance.value
-                } else {
-                    panic!("Invalid type for rhs")
-                }
-            } else {
-                panic!("Invalid type for rhs")
-            }
-        } else {
-            panic!("Invalid type for rhs")
+    /// `Number(x)` converts strings and booleans to numbers, `Number()` is 0
+    fn construct(&self, args: Vec<Rc<RefCell<Value>>>) -> Result<Value, RuntimeError> {
+        let value = match args.as_slice() {
+            [] => 0.0,
+            [value] => match &*value.borrow() {
+                Value::Boolean(b) => f64::from(u8::from(*b)),
+                Value::Number(n) => *n,
+                Value::String(text) => NumberClass::parse(text)?,
+                other => raise(
+                    ErrorKind::Type,
+                    format!("Cannot convert {} to a number", other),
+                )?,
+            },
+            _ => raise(
+                ErrorKind::Arity,
+                format!("Number expects at most 1 argument, got {}", args.len()),
+            )?,
         };
+        Ok(Value::Number(value))
+    }
 
-        let result = match method {
-            MagicMethod::Add => self.value + rhs,
-            MagicMethod::Sub => self.value - rhs,
-            MagicMethod::Mul => self.value * rhs,
-            MagicMethod::Div => self.value / rhs,
-            MagicMethod::Equal => return Rc::new(RefCell::new(Value::Boolean(self.value == rhs))),
-            MagicMethod::NotEqual => {
-                return Rc::new(RefCell::new(Value::Boolean(self.value != rhs)))
-            }
-            MagicMethod::GreaterThan => {
-                return Rc::new(RefCell::new(Value::Boolean(self.value > rhs)))
-            }
-            MagicMethod::LessThan => {
-                return Rc::new(RefCell::new(Value::Boolean(self.value < rhs)))
-            }
+    /// The methods of numbers, like `x.abs()`
+    fn method(&self, name: &str) -> Option<Function> {
+        let method = match name {
+            "abs" => |args: Vec<Rc<RefCell<Value>>>| match &*args[0].borrow() {
+                Value::Number(n) => Ok(Value::Number(n.abs())),
+                other => raise(ErrorKind::Type, format!("{} is not a number", other)),
+            },
+            _ => return None,
         };
+        Some(Function::Builtin(BuiltinFunction::new(method)))
+    }
+}
 
-        Rc::new(RefCell::new(Value::ClassInstance(Rc::new(
-            NumberInstance { value: result },
-        ))))
+impl NumberClass {
+    /// Read a number from a string, ignoring the whitespace around it
+    fn parse(text: &str) -> Result<f64, RuntimeError> {
+        text.trim().parse().or_else(|_| {
+            raise(
+                ErrorKind::Value,
+                format!("Cannot parse '{}' as a number", text),
+            )
+        })
     }
 }
@@ -0,0 +1,103 @@
+//! What the programs run by a VM can access outside of it, like files or the environment variables
+
+use std::path::{Path, PathBuf};
+
+use crate::errors::{raise, ErrorKind, RuntimeError};
+
+/// The capabilities granted to the programs of a VM, given to `VM::new`.
+/// The default grants everything, `VmPermissions::none()` nothing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VmPermissions {
+    /// Reading the standard input, with `Std.input`
+    pub input: bool,
+    /// Reading the environment variables, with `Std.env`
+    pub env: bool,
+    /// Reading the clock and sleeping, with `Std.Time`
+    pub time: bool,
+    /// The files `open(path)` can read
+    pub read: Access,
+    /// The files `open(path, "w")` and `open(path, "a")` can write
+    pub write: Access,
+}
+
+/// The files a program can access
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Access {
+    All,
+    None,
+    /// The files in these directories, or these files
+    Paths(Vec<PathBuf>),
+}
+
+impl Default for VmPermissions {
+    fn default() -> Self {
+        VmPermissions {
+            input: true,
+            env: true,
+            time: true,
+            read: Access::All,
+            write: Access::All,
+        }
+    }
+}
+
+impl VmPermissions {
+    /// No capability at all, the programs can only compute and print
+    pub fn none() -> Self {
+        VmPermissions {
+            input: false,
+            env: false,
+            time: false,
+            read: Access::None,
+            write: Access::None,
+        }
+    }
+
+    /// Fail if a capability isn't granted, `feature` being what needs it, like `Std.input`
+    pub(crate) fn require(
+        granted: bool,
+        feature: &str,
+        permission: &str,
+    ) -> Result<(), RuntimeError> {
+        if granted {
+            return Ok(());
+        }
+        raise(
+            ErrorKind::Permission,
+            format!(
+                "Permission denied: {} requires the '{}' permission",
+                feature, permission
+            ),
+        )
+    }
+}
+
+impl Access {
+    /// Check if a file can be accessed. Paths are compared once resolved, so `data/../secret` isn't in `data`.
+    pub fn allows(&self, path: &Path) -> bool {
+        match self {
+            Access::All => true,
+            Access::None => false,
+            Access::Paths(allowed) => resolve(path).is_some_and(|path| {
+                allowed
+                    .iter()
+                    .filter_map(|allowed| resolve(allowed))
+                    .any(|allowed| path.starts_with(allowed))
+            }),
+        }
+    }
+}
+
+/// The absolute form of a path, without `.` and `..` and with its links followed.
+/// The file may not exist yet, but its directory must.
+fn resolve(path: &Path) -> Option<PathBuf> {
+    if let Ok(path) = path.canonicalize() {
+        return Some(path);
+    }
+    let name = path.file_name()?;
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    Some(directory.canonicalize().ok()?.join(name))
+}
@@ -0,0 +1,170 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    class::{Class, ClassInstance, Items},
+    errors::{raise, unsupported, ErrorKind, RuntimeError},
+    BuiltinFunction, Function, MagicMethod, Value,
+};
+
+#[derive(Debug)]
+pub struct RangeClass;
+
+impl Class for RangeClass {
+    fn name(&self) -> &str {
+        "Range"
+    }
+}
+
+/// A range of numbers, created with `start..end` or `start..=end`.
+/// It is lazy: the numbers are computed while iterating, so `0..1000000` doesn't allocate anything.
+#[derive(Clone)]
+pub struct RangeInstance {
+    pub start: f64,
+    pub end: f64,
+    pub step: f64,
+    pub inclusive: bool,
+}
+
+impl RangeInstance {
+    pub fn new(start: f64, end: f64, inclusive: bool) -> Self {
+        RangeInstance {
+            start,
+            end,
+            step: 1.0,
+            inclusive,
+        }
+    }
+
+    /// The number of values in the range
+    pub fn len(&self) -> usize {
+        let span = (self.end - self.start) / self.step;
+        if span < 0.0 {
+            return 0;
+        }
+        if self.inclusive {
+            span.floor() as usize + 1
+        } else {
+            span.ceil() as usize
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: usize) -> f64 {
+        self.start + index as f64 * self.step
+    }
+
+    pub fn contains(&self, value: f64) -> bool {
+        let steps = (value - self.start) / self.step;
+        steps >= 0.0 && steps.fract() == 0.0 && (steps as usize) < self.len()
+    }
+
+    /// The same values, in the opposite order
+    pub fn reverse(&self) -> RangeInstance {
+        if self.is_empty() {
+            return RangeInstance::new(self.start, self.start, false);
+        }
+        RangeInstance {
+            start: self.get(self.len() - 1),
+            end: self.start,
+            step: -self.step,
+            inclusive: true,
+        }
+    }
+
+    fn number(value: f64) -> Rc<RefCell<Value>> {
+        Rc::new(RefCell::new(Value::Number(value)))
+    }
+}
+
+impl std::fmt::Debug for RangeInstance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let operator = if self.inclusive { "..=" } else { ".." };
+        if self.step == 1.0 {
+            write!(f, "{}{}{}", self.start, operator, self.end)
+        } else {
+            write!(
+                f,
+                "({}{}{}).step({})",
+                self.start, operator, self.end, self.step
+            )
+        }
+    }
+}
+
+impl ClassInstance for RangeInstance {
+    fn class_name(&self) -> &str {
+        "Range"
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    fn iterate(&self) -> Option<Items> {
+        let range = self.clone();
+        Some(Box::new((0..range.len()).map(move |index| {
+            Ok(RangeInstance::number(range.get(index)))
+        })))
+    }
+
+    fn get_field(&self, name: &str) -> Option<Rc<RefCell<Value>>> {
+        let range = self.clone();
+        let function = match name {
+            "len" => BuiltinFunction::new(move |_args| Ok(Value::Number(range.len() as f64))),
+            "contains" => BuiltinFunction::new(move |args| {
+                let contained = match args.first().and_then(|arg| arg.borrow().as_number()) {
+                    Some(n) => range.contains(n),
+                    None => false,
+                };
+                Ok(Value::Boolean(contained))
+            }),
+            "reverse" => BuiltinFunction::new(move |_args| {
+                Ok(Value::ClassInstance(Rc::new(range.reverse())))
+            }),
+            "step" => BuiltinFunction::new(move |args| {
+                let Some(step) = args.first().and_then(|arg| arg.borrow().as_number()) else {
+                    return raise(ErrorKind::Type, "step expects a number");
+                };
+                if step == 0.0 {
+                    return raise(ErrorKind::Value, "The step of a range can't be zero");
+                }
+                Ok(Value::ClassInstance(Rc::new(RangeInstance {
+                    step,
+                    ..range.clone()
+                })))
+            }),
+            _ => return None,
+        };
+        Some(Rc::new(RefCell::new(Value::Function(Function::Builtin(
+            function,
+        )))))
+    }
+
+    fn call_magic(
+        &self,
+        method: MagicMethod,
+        args: Vec<Rc<RefCell<Value>>>,
+    ) -> Result<Rc<RefCell<Value>>, RuntimeError> {
+        match method {
+            MagicMethod::Equal | MagicMethod::NotEqual => {
+                let rhs = args[1].borrow();
+                let equal = rhs
+                    .as_any()
+                    .downcast_ref::<RangeInstance>()
+                    .is_some_and(|rhs| {
+                        self.start == rhs.start
+                            && self.end == rhs.end
+                            && self.step == rhs.step
+                            && self.inclusive == rhs.inclusive
+                    });
+                Ok(Rc::new(RefCell::new(Value::Boolean(
+                    equal == (method == MagicMethod::Equal),
+                ))))
+            }
+            _ => unsupported(method, self.class_name(), &args),
+        }
+    }
+}
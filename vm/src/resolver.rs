@@ -0,0 +1,497 @@
+use std::{collections::HashMap, rc::Rc};
+
+use parser::{expr::Expr, pattern::Pattern, statement::Statement};
+
+use crate::{
+    class::Class,
+    errors::{ErrorKind, RuntimeError},
+    Globals,
+};
+
+/// Where the value of a variable is stored while the program runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Variable {
+    /// A slot of the running function
+    Local(u32),
+    /// A variable of the code around the function, captured when the function was created
+    Upvalue(u32),
+    /// A global variable, by its index in `Globals`
+    Global(u32),
+}
+
+/// A variable of the code creating a function, captured by the function when it is created
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Capture {
+    /// A slot of the function creating it
+    Local(u32),
+    /// A variable captured by the function creating it
+    Upvalue(u32),
+}
+
+/// What an identifier designates
+pub(crate) enum Resolution {
+    Variable(Variable),
+    /// A builtin class, like `Number` or `Std`, when no variable has its name
+    Class(Rc<dyn Class>),
+}
+
+/// A variable found by the resolver, with how it was declared
+#[derive(Clone, Copy)]
+struct Found {
+    variable: Variable,
+    /// Whether the variable was declared with `let`, `const` or as a parameter.
+    /// Functions can assign the declared variables of the code around them, but assigning any other name
+    /// creates a variable of their own.
+    declared: bool,
+    constant: bool,
+}
+
+struct Binding {
+    name: Rc<str>,
+    slot: u32,
+    declared: bool,
+    constant: bool,
+}
+
+/// The variables of a function being compiled, or of the top level of the program
+#[derive(Default)]
+struct FunctionScope {
+    /// The scopes opened by the blocks of the function, innermost last.
+    /// The first one holds the parameters and the variables of the function, except at the top level of the
+    /// program, whose variables are global.
+    scopes: Vec<Vec<Binding>>,
+    /// The number of slots the function needs. Slots are never shared by two variables, so that a slot
+    /// captured by a closure always holds the same variable.
+    slots: u32,
+    captures: Vec<(Rc<str>, Capture, Found)>,
+}
+
+/// Resolves the names of a program to the slots of the functions, their captured variables and the global
+/// variables, so that they are found by index while running. The names that designate nothing are reported
+/// before the program runs.
+pub(crate) struct Resolver<'a> {
+    /// The functions being compiled, innermost last, the first being the top level of the program
+    functions: Vec<FunctionScope>,
+    globals: &'a mut Globals,
+    classes: &'a HashMap<String, Rc<dyn Class>>,
+}
+
+impl<'a> Resolver<'a> {
+    pub fn new(globals: &'a mut Globals, classes: &'a HashMap<String, Rc<dyn Class>>) -> Self {
+        Resolver {
+            functions: vec![FunctionScope {
+                scopes: vec![Vec::new()],
+                ..FunctionScope::default()
+            }],
+            globals,
+            classes,
+        }
+    }
+
+    pub fn begin_function(&mut self) {
+        self.functions.push(FunctionScope {
+            scopes: vec![Vec::new()],
+            ..FunctionScope::default()
+        });
+    }
+
+    /// End the innermost function, returning the number of slots it needs and the variables it captures
+    pub fn end_function(&mut self) -> (u32, Vec<Capture>) {
+        let function = self.functions.pop().expect("No function is being compiled");
+        let captures = function
+            .captures
+            .into_iter()
+            .map(|(_, capture, _)| capture)
+            .collect();
+        (function.slots, captures)
+    }
+
+    pub fn begin_scope(&mut self) {
+        self.function().scopes.push(Vec::new());
+    }
+
+    pub fn end_scope(&mut self) {
+        self.function().scopes.pop();
+    }
+
+    fn function(&mut self) -> &mut FunctionScope {
+        self.functions.last_mut().unwrap()
+    }
+
+    /// Whether the code being compiled is the top level of the program, outside of any block
+    fn at_top_level(&self) -> bool {
+        self.functions.len() == 1 && self.functions[0].scopes.len() == 1
+    }
+
+    /// Declare a variable with `let`, `const` or as a parameter, in the innermost scope
+    pub fn declare(&mut self, name: &str, constant: bool) -> Result<Variable, RuntimeError> {
+        if self.at_top_level() {
+            let index = self.globals.index(name);
+            let global = &mut self.globals.variables[index as usize];
+            if global.constant {
+                return Err(redeclared(name));
+            }
+            global.declared = true;
+            global.constant = constant;
+            return Ok(Variable::Global(index));
+        }
+        let function = self.function();
+        let scope = function.scopes.last_mut().unwrap();
+        if let Some(binding) = scope.iter_mut().find(|binding| *binding.name == *name) {
+            if binding.constant {
+                return Err(redeclared(name));
+            }
+            binding.declared = true;
+            binding.constant = constant;
+            return Ok(Variable::Local(binding.slot));
+        }
+        let slot = function.slots;
+        function.slots += 1;
+        scope.push(Binding {
+            name: name.into(),
+            slot,
+            declared: true,
+            constant,
+        });
+        Ok(Variable::Local(slot))
+    }
+
+    /// The variable of the function with this name, like a function or class declared in it
+    pub fn define(&mut self, name: &str) -> Variable {
+        if self.functions.len() == 1 {
+            return Variable::Global(self.globals.index(name));
+        }
+        let function = self.function();
+        if let Some(binding) = function.scopes[0]
+            .iter()
+            .find(|binding| *binding.name == *name)
+        {
+            return Variable::Local(binding.slot);
+        }
+        let slot = function.slots;
+        function.slots += 1;
+        function.scopes[0].push(Binding {
+            name: name.into(),
+            slot,
+            declared: false,
+            constant: false,
+        });
+        Variable::Local(slot)
+    }
+
+    /// The variable assigned by `name = ...`: the innermost variable of the function with this name, or else
+    /// the innermost declared variable of the code around it. If there is none, it is a variable of the function.
+    pub fn assign(&mut self, name: &str) -> Result<Variable, RuntimeError> {
+        let depth = self.functions.len() - 1;
+        let found = match self.find_local(depth, name) {
+            Some(found) => Some(found),
+            None if depth == 0 => Some(self.global(name)),
+            None => self.find(depth, name, true),
+        };
+        match found {
+            Some(found) if found.constant => Err(RuntimeError::new(
+                ErrorKind::Constant,
+                format!("Cannot assign to constant '{}'", name),
+            )),
+            Some(found) => Ok(found.variable),
+            None => Ok(self.define(name)),
+        }
+    }
+
+    /// The variable an identifier reads, if there is one
+    pub fn lookup(&mut self, name: &str) -> Option<Variable> {
+        let depth = self.functions.len() - 1;
+        self.find(depth, name, false).map(|found| found.variable)
+    }
+
+    /// The variable or builtin class an identifier designates
+    pub fn resolve(&mut self, name: &str) -> Result<Resolution, RuntimeError> {
+        if let Some(variable) = self.lookup(name) {
+            return Ok(Resolution::Variable(variable));
+        }
+        match self.classes.get(name) {
+            Some(class) => Ok(Resolution::Class(Rc::clone(class))),
+            None => Err(RuntimeError::new(
+                ErrorKind::Name,
+                format!("Variable or class '{}' not found", name),
+            )),
+        }
+    }
+
+    /// Declare the variables a function or the program assigns anywhere in its body, so that they are its own
+    /// variables from its start, even for the code before the assignments (like the functions declared before).
+    /// At the top level, they are the global variables.
+    pub fn hoist(&mut self, statements: &[Statement]) {
+        let mut names = Vec::new();
+        for statement in statements {
+            statement_names(statement, &mut names);
+        }
+        self.hoist_names(names);
+    }
+
+    /// Declare the variables assigned in an expression evaluated by a function of its own, like the element
+    /// of a generator
+    pub fn hoist_expr(&mut self, expr: &Expr) {
+        let mut names = Vec::new();
+        expr_names(expr, &mut names);
+        self.hoist_names(names);
+    }
+
+    fn hoist_names(&mut self, names: Vec<(&str, bool)>) {
+        let depth = self.functions.len() - 1;
+        for (name, defined) in names {
+            if depth == 0 {
+                self.globals.index(name);
+            } else if defined
+                || (self.find_local(depth, name).is_none()
+                    && self.find(depth, name, true).is_none())
+            {
+                self.define(name);
+            }
+        }
+    }
+
+    /// Find a variable of a function, in its innermost scopes first
+    fn find_local(&self, depth: usize, name: &str) -> Option<Found> {
+        self.functions[depth]
+            .scopes
+            .iter()
+            .rev()
+            .flat_map(|scope| scope.iter().rev())
+            .find(|binding| *binding.name == *name)
+            .map(|binding| Found {
+                variable: Variable::Local(binding.slot),
+                declared: binding.declared,
+                constant: binding.constant,
+            })
+    }
+
+    /// Find a variable seen by a function: its own, or else one of the code around it that it captures,
+    /// or else a global one. With `declared_only`, the variables of the code around it that were not declared
+    /// are skipped.
+    fn find(&mut self, depth: usize, name: &str, declared_only: bool) -> Option<Found> {
+        if let Some(found) = self.find_local(depth, name) {
+            return Some(found);
+        }
+        if depth == 0 {
+            let index = self.globals.find(name)?;
+            let global = &self.globals.variables[index as usize];
+            return Some(Found {
+                variable: Variable::Global(index),
+                declared: global.declared,
+                constant: global.constant,
+            })
+            .filter(|found| found.declared || !declared_only);
+        }
+        let function = &self.functions[depth];
+        if let Some(index) = function
+            .captures
+            .iter()
+            .position(|(captured, _, _)| **captured == *name)
+        {
+            let found = function.captures[index].2;
+            return Some(Found {
+                variable: Variable::Upvalue(index as u32),
+                ..found
+            })
+            .filter(|found| found.declared || !declared_only);
+        }
+        let found = self.find(depth - 1, name, declared_only)?;
+        let capture = match found.variable {
+            Variable::Local(slot) => Capture::Local(slot),
+            Variable::Upvalue(index) => Capture::Upvalue(index),
+            Variable::Global(_) => return Some(found),
+        };
+        let captures = &mut self.functions[depth].captures;
+        captures.push((name.into(), capture, found));
+        Some(Found {
+            variable: Variable::Upvalue(captures.len() as u32 - 1),
+            ..found
+        })
+    }
+
+    fn global(&mut self, name: &str) -> Found {
+        let index = self.globals.index(name);
+        let global = &self.globals.variables[index as usize];
+        Found {
+            variable: Variable::Global(index),
+            declared: global.declared,
+            constant: global.constant,
+        }
+    }
+}
+
+fn redeclared(name: &str) -> RuntimeError {
+    RuntimeError::new(
+        ErrorKind::Constant,
+        format!("Cannot redeclare constant '{}'", name),
+    )
+}
+
+/// Collect the names a statement assigns or defines in the function running it, with whether they are defined
+/// (by `fn`, `class`, `enum` or `interface`) rather than assigned. The `let` declarations are left out,
+/// as they only live in their block, and so are the functions declared in the statement.
+fn statement_names<'s>(statement: &'s Statement, names: &mut Vec<(&'s str, bool)>) {
+    match statement {
+        Statement::Return(expr) | Statement::Expr(expr) | Statement::Defer(expr) => {
+            expr_names(expr, names)
+        }
+        Statement::Block(statements) => {
+            for statement in statements {
+                statement_names(statement, names);
+            }
+        }
+        Statement::Fn { name, .. }
+        | Statement::Enum { name, .. }
+        | Statement::Interface { name, .. } => names.push((name, true)),
+        Statement::Assign(name, expr) => {
+            expr_names(expr, names);
+            names.push((name, false));
+        }
+        Statement::Destructure(pattern, expr) => {
+            expr_names(expr, names);
+            pattern_names(pattern, names);
+        }
+        Statement::Let { value, .. } => expr_names(value, names),
+        Statement::If {
+            condition,
+            body,
+            else_body,
+        } => {
+            expr_names(condition, names);
+            for statement in body.iter().chain(else_body) {
+                statement_names(statement, names);
+            }
+        }
+        Statement::Match { subject, arms } => {
+            expr_names(subject, names);
+            for arm in arms {
+                if let Some(guard) = &arm.guard {
+                    expr_names(guard, names);
+                }
+                for statement in &arm.body {
+                    statement_names(statement, names);
+                }
+            }
+        }
+        Statement::For { iterable, body, .. } => {
+            expr_names(iterable, names);
+            for statement in body {
+                statement_names(statement, names);
+            }
+        }
+        Statement::Assert {
+            condition, message, ..
+        } => {
+            expr_names(condition, names);
+            if let Some(message) = message {
+                expr_names(message, names);
+            }
+        }
+        Statement::With { resource, body, .. } => {
+            expr_names(resource, names);
+            for statement in body {
+                statement_names(statement, names);
+            }
+        }
+        Statement::Class { name, statics, .. } => {
+            for (_, value) in statics {
+                expr_names(value, names);
+            }
+            names.push((name, true));
+        }
+        Statement::SetField { object, value, .. } => {
+            expr_names(object, names);
+            expr_names(value, names);
+        }
+        Statement::Located { statement, .. } => statement_names(statement, names),
+        Statement::Macro { .. } => {}
+    }
+}
+
+/// Collect the names assigned by the blocks of an expression, which run in the function evaluating it
+fn expr_names<'s>(expr: &'s Expr, names: &mut Vec<(&'s str, bool)>) {
+    let mut exprs = |exprs: &mut dyn Iterator<Item = &'s Expr>| {
+        for expr in exprs {
+            expr_names(expr, names);
+        }
+    };
+    match expr {
+        Expr::Number(_) | Expr::Boolean(_) | Expr::Identifier(_) | Expr::String(_) => {}
+        Expr::List(items) | Expr::Tuple(items) | Expr::Acessor(items) => exprs(&mut items.iter()),
+        Expr::Map(entries) => exprs(&mut entries.iter().flat_map(|(key, value)| [key, value])),
+        Expr::Call(function, args) => exprs(&mut std::iter::once(&**function).chain(args)),
+        Expr::BinaryOp { lhs, rhs, .. } => exprs(&mut [&**lhs, &**rhs].into_iter()),
+        Expr::Range { start, end, .. } => exprs(&mut [&**start, &**end].into_iter()),
+        Expr::Is { value, .. } | Expr::Located { expr: value, .. } => expr_names(value, names),
+        Expr::Block(statements) => {
+            for statement in statements {
+                statement_names(statement, names);
+            }
+        }
+        Expr::If {
+            condition,
+            body,
+            else_body,
+        } => {
+            expr_names(condition, names);
+            for statement in body.iter().chain(else_body) {
+                statement_names(statement, names);
+            }
+        }
+        Expr::Match { subject, arms } => {
+            expr_names(subject, names);
+            for arm in arms {
+                if let Some(guard) = &arm.guard {
+                    expr_names(guard, names);
+                }
+                for statement in &arm.body {
+                    statement_names(statement, names);
+                }
+            }
+        }
+        Expr::ListComprehension {
+            element,
+            comprehension,
+        } => {
+            expr_names(&comprehension.iterable, names);
+            if let Some(condition) = &comprehension.condition {
+                expr_names(condition, names);
+            }
+            expr_names(element, names);
+        }
+        Expr::MapComprehension {
+            key,
+            value,
+            comprehension,
+        } => {
+            expr_names(&comprehension.iterable, names);
+            if let Some(condition) = &comprehension.condition {
+                expr_names(condition, names);
+            }
+            expr_names(key, names);
+            expr_names(value, names);
+        }
+        // The element of a generator is evaluated by a function of its own
+        Expr::Generator { comprehension, .. } => expr_names(&comprehension.iterable, names),
+    }
+}
+
+/// Collect the names bound by the pattern of a destructuring assignment
+fn pattern_names<'s>(pattern: &'s Pattern, names: &mut Vec<(&'s str, bool)>) {
+    match pattern {
+        Pattern::Binding(name) | Pattern::Rest(Some(name)) => names.push((name, false)),
+        Pattern::Alternatives(patterns)
+        | Pattern::List(patterns)
+        | Pattern::Tuple(patterns)
+        | Pattern::Class {
+            fields: patterns, ..
+        } => {
+            for pattern in patterns {
+                pattern_names(pattern, names);
+            }
+        }
+        Pattern::Typed { pattern, .. } => pattern_names(pattern, names),
+        Pattern::Wildcard | Pattern::Literal(_) | Pattern::Rest(None) => {}
+    }
+}
@@ -0,0 +1,344 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use parser::class::MethodSignature;
+
+use crate::{
+    class::{Class, ClassInstance},
+    errors::{arity_error, raise, unsupported, ErrorKind, RuntimeError},
+    gc::{self, Trace, Tracer},
+    values_equal, Function, MagicMethod, Value,
+};
+
+/// A class declared in a script, like `class Money(amount, currency) implements Comparable { ... }`.
+/// Calling the class with the values of its fields creates an instance: `Money(5, "EUR")`.
+#[derive(Debug)]
+pub struct ScriptClass {
+    definition: Rc<ClassDefinition>,
+}
+
+/// The members of a script class, shared by the class and its instances
+#[derive(Debug)]
+pub struct ClassDefinition {
+    pub name: String,
+    pub fields: Vec<String>,
+    pub methods: HashMap<String, Function>,
+    pub getters: HashMap<String, Function>,
+    pub setters: HashMap<String, Function>,
+    pub static_methods: HashMap<String, Function>,
+    pub static_fields: RefCell<HashMap<String, Rc<RefCell<Value>>>>,
+    /// The interfaces the class implements, checked when the class is declared
+    pub interfaces: Vec<InterfaceInstance>,
+}
+
+impl ScriptClass {
+    pub fn new(definition: ClassDefinition) -> Result<Self, RuntimeError> {
+        definition.check_interfaces()?;
+        let definition = Rc::new(definition);
+        gc::track(&definition);
+        Ok(ScriptClass { definition })
+    }
+}
+
+impl ClassDefinition {
+    /// Check that the class has the methods of its interfaces, with the same number of parameters
+    fn check_interfaces(&self) -> Result<(), RuntimeError> {
+        for interface in &self.interfaces {
+            for signature in interface.methods.iter() {
+                let Some(method) = self.methods.get(&signature.name) else {
+                    return raise(
+                        ErrorKind::Type,
+                        format!(
+                            "Class '{}' is missing the method '{}' of interface '{}'",
+                            self.name, signature.name, interface.name
+                        ),
+                    );
+                };
+                if let Function::UserDefined { code, .. } = method {
+                    if code.params.len() != signature.params.len() {
+                        return raise(
+                            ErrorKind::Type,
+                            format!(
+                            "Method '{}' of class '{}' takes {} parameters, but interface '{}' declares {}",
+                            signature.name,
+                            self.name,
+                            code.params.len(),
+                            interface.name,
+                            signature.params.len()
+                            ),
+                        );
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The methods and static fields of a class can refer to its instances, or to the class itself
+impl Trace for ClassDefinition {
+    fn trace(&self, tracer: &mut Tracer) {
+        let methods = [
+            &self.methods,
+            &self.getters,
+            &self.setters,
+            &self.static_methods,
+        ];
+        for method in methods.into_iter().flat_map(|methods| methods.values()) {
+            tracer.function(method);
+        }
+        if let Ok(fields) = self.static_fields.try_borrow() {
+            for field in fields.values() {
+                tracer.visit(field);
+            }
+        }
+    }
+
+    fn clear(&self) {
+        if let Ok(mut fields) = self.static_fields.try_borrow_mut() {
+            fields.clear();
+        }
+    }
+}
+
+impl Class for ScriptClass {
+    fn name(&self) -> &str {
+        &self.definition.name
+    }
+
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.visit(&self.definition);
+    }
+
+    fn get_static(&self, name: &str) -> Option<Rc<RefCell<Value>>> {
+        if let Some(field) = self.definition.static_fields.borrow().get(name) {
+            return Some(Rc::clone(field));
+        }
+        let method = self.definition.static_methods.get(name)?;
+        Some(Rc::new(RefCell::new(Value::Function(method.clone()))))
+    }
+
+    fn set_static(&self, name: &str, value: Rc<RefCell<Value>>) -> bool {
+        let mut fields = self.definition.static_fields.borrow_mut();
+        match fields.get_mut(name) {
+            Some(field) => {
+                *field = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn construct(&self, args: Vec<Rc<RefCell<Value>>>) -> Result<Value, RuntimeError> {
+        let definition = &self.definition;
+        if args.len() != definition.fields.len() {
+            return Err(arity_error(
+                &definition.name,
+                definition.fields.len(),
+                args.len(),
+            ));
+        }
+        let fields = Rc::new(RefCell::new(args));
+        gc::track(&fields);
+        Ok(Value::ClassInstance(Rc::new(ScriptInstance {
+            class: Rc::clone(definition),
+            fields,
+        })))
+    }
+}
+
+/// An instance of a class declared in a script. Its fields are read by name, and its methods are called
+/// with the instance as their first parameter: `m.compare(other)` calls `compare(m, other)`.
+/// The fields are shared between the references to the instance, so assigning one is seen by all of them.
+#[derive(Clone)]
+pub struct ScriptInstance {
+    class: Rc<ClassDefinition>,
+    fields: Rc<RefCell<Vec<Rc<RefCell<Value>>>>>,
+}
+
+impl ScriptInstance {
+    /// Check if the class of the instance declares that it implements the interface
+    pub fn implements(&self, interface: &InterfaceInstance) -> bool {
+        self.class
+            .interfaces
+            .iter()
+            .any(|implemented| Rc::ptr_eq(&implemented.methods, &interface.methods))
+    }
+
+    /// The getter of a property, called by the VM when reading it
+    pub fn getter(&self, name: &str) -> Option<&Function> {
+        self.class.getters.get(name)
+    }
+
+    /// The setter of a property, called by the VM when assigning it
+    pub fn setter(&self, name: &str) -> Option<&Function> {
+        self.class.setters.get(name)
+    }
+
+    /// The method called by `with` when it starts or ends using the instance, if its class is a resource:
+    /// one declaring both `enter(self)` and `exit(self, failed)`
+    pub fn resource_method(&self, method: MagicMethod) -> Option<&Function> {
+        let enter = self.class.methods.get("enter")?;
+        let exit = self.class.methods.get("exit")?;
+        match method {
+            MagicMethod::Enter => Some(enter),
+            MagicMethod::Exit => Some(exit),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Debug for ScriptInstance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let fields: Vec<String> = self
+            .fields
+            .borrow()
+            .iter()
+            .map(|field| field.borrow().to_string())
+            .collect();
+        write!(f, "{}({})", self.class.name, fields.join(", "))
+    }
+}
+
+impl ClassInstance for ScriptInstance {
+    fn class_name(&self) -> &str {
+        &self.class.name
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    fn set_field(&self, name: &str, value: Rc<RefCell<Value>>) -> Result<bool, RuntimeError> {
+        match self.class.fields.iter().position(|field| field == name) {
+            Some(position) => {
+                self.fields.borrow_mut()[position] = value;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn destructure(&self) -> Option<Vec<Rc<RefCell<Value>>>> {
+        Some(self.fields.borrow().clone())
+    }
+
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.visit(&self.class);
+        tracer.visit(&self.fields);
+    }
+
+    fn get_field(&self, name: &str) -> Option<Rc<RefCell<Value>>> {
+        if let Some(position) = self.class.fields.iter().position(|field| field == name) {
+            return Some(Rc::clone(&self.fields.borrow()[position]));
+        }
+        let method = self.class.methods.get(name)?;
+        let receiver = Rc::new(RefCell::new(Value::ClassInstance(Rc::new(self.clone()))));
+        Some(Rc::new(RefCell::new(Value::Function(Function::Bound {
+            receiver,
+            function: Box::new(method.clone()),
+        }))))
+    }
+
+    fn call_magic(
+        &self,
+        method: MagicMethod,
+        args: Vec<Rc<RefCell<Value>>>,
+    ) -> Result<Rc<RefCell<Value>>, RuntimeError> {
+        match method {
+            MagicMethod::Equal | MagicMethod::NotEqual => {
+                let rhs = args[1].borrow();
+                let equal = rhs
+                    .as_any()
+                    .downcast_ref::<ScriptInstance>()
+                    .is_some_and(|rhs| {
+                        Rc::ptr_eq(&self.class, &rhs.class)
+                            && self
+                                .fields
+                                .borrow()
+                                .iter()
+                                .zip(rhs.fields.borrow().iter())
+                                .all(|(lhs, rhs)| values_equal(lhs, rhs))
+                    });
+                Ok(Rc::new(RefCell::new(Value::Boolean(
+                    equal == (method == MagicMethod::Equal),
+                ))))
+            }
+            _ => unsupported(method, &self.class.name, &args),
+        }
+    }
+}
+
+/// An interface declared in a script, like `interface Comparable { fn compare(self, other) }`.
+/// Script classes implement it by declaring it, builtin classes by having its methods.
+#[derive(Clone)]
+pub struct InterfaceInstance {
+    pub name: String,
+    pub methods: Rc<Vec<MethodSignature>>,
+}
+
+impl InterfaceInstance {
+    pub fn new(name: String, methods: Vec<MethodSignature>) -> Self {
+        InterfaceInstance {
+            name,
+            methods: Rc::new(methods),
+        }
+    }
+
+    /// Check if a value implements the interface, for `value is Interface`
+    pub fn is_implemented_by(&self, value: &Value) -> bool {
+        let Value::ClassInstance(instance) = value else {
+            return self
+                .methods
+                .iter()
+                .all(|method| value.method(&method.name).is_some());
+        };
+        match instance.as_any().downcast_ref::<ScriptInstance>() {
+            Some(instance) => instance.implements(self),
+            None => self.methods.iter().all(|method| {
+                instance
+                    .get_field(&method.name)
+                    .is_some_and(|field| matches!(*field.borrow(), Value::Function(_)))
+            }),
+        }
+    }
+}
+
+impl std::fmt::Debug for InterfaceInstance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<interface {}>", self.name)
+    }
+}
+
+impl ClassInstance for InterfaceInstance {
+    fn class_name(&self) -> &str {
+        "Interface"
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    fn get_field(&self, _name: &str) -> Option<Rc<RefCell<Value>>> {
+        None
+    }
+
+    fn call_magic(
+        &self,
+        method: MagicMethod,
+        args: Vec<Rc<RefCell<Value>>>,
+    ) -> Result<Rc<RefCell<Value>>, RuntimeError> {
+        match method {
+            MagicMethod::Equal | MagicMethod::NotEqual => {
+                let rhs = args[1].borrow();
+                let same = rhs
+                    .as_any()
+                    .downcast_ref::<InterfaceInstance>()
+                    .is_some_and(|rhs| Rc::ptr_eq(&self.methods, &rhs.methods));
+                Ok(Rc::new(RefCell::new(Value::Boolean(
+                    same == (method == MagicMethod::Equal),
+                ))))
+            }
+            _ => unsupported(method, self.class_name(), &args),
+        }
+    }
+}
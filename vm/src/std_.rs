@@ -1,23 +1,29 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{cell::RefCell, fmt::Write, rc::Rc};
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, TimeZone, Utc};
 
 use crate::{
     class::{Class, ClassInstance},
-    number::NumberInstance,
-    string::StringInstance,
+    errors::{raise, unsupported, ErrorKind, RuntimeError},
+    gc,
+    map::MapInstance,
+    permissions::VmPermissions,
     BuiltinFunction, Function, MagicMethod, Value,
 };
 
+/// The standard library, accessed with `Std`: `Std.print`, `Std.input`, `Std.env`, `Std.Time`, `Std.Gc`.
+/// The functions needing a permission the VM doesn't have fail when they are called.
 #[derive(Debug)]
-pub struct StdInstance;
+pub struct StdClass {
+    pub permissions: Rc<VmPermissions>,
+}
 
-impl ClassInstance for StdInstance {
-    fn as_any(&self) -> &dyn std::any::Any {
-        self
+impl Class for StdClass {
+    fn name(&self) -> &str {
+        "Std"
     }
 
-    fn get_field(&self, name: &str) -> Option<Rc<RefCell<Value>>> {
+    fn get_static(&self, name: &str) -> Option<Rc<RefCell<Value>>> {
         match name {
             "print" => Some(Rc::new(RefCell::new(Value::Function(Function::Builtin(
                 BuiltinFunction::new(|args| {
@@ -25,85 +31,178 @@ impl ClassInstance for StdInstance {
                         print!("{:}", arg.borrow());
                     }
                     println!();
-                    Value::None
-                }),
-            ))))),
-            "input" => Some(Rc::new(RefCell::new(Value::Function(Function::Builtin(
-                BuiltinFunction::new(|args| {
-                    let mut input = String::new();
-                    std::io::stdin()
-                        .read_line(&mut input)
-                        .expect("Failed to read line");
-                    Value::ClassInstance(Rc::new(StringInstance {
-                        value: input.trim().to_string(),
-                    }))
+                    Ok(Value::None)
                 }),
             ))))),
-            "Time" => Some(Rc::new(RefCell::new(Value::ClassInstance(Rc::new(
-                TimeInstance,
-            ))))),
+            "input" => {
+                let granted = self.permissions.input;
+                Some(Rc::new(RefCell::new(Value::Function(Function::Builtin(
+                    BuiltinFunction::new(move |_args| {
+                        VmPermissions::require(granted, "Std.input", "input")?;
+                        let mut input = String::new();
+                        std::io::stdin().read_line(&mut input).or_else(|error| {
+                            raise(
+                                ErrorKind::Io,
+                                format!("Failed to read the input: {}", error),
+                            )
+                        })?;
+                        Ok(Value::String(input.trim().into()))
+                    }),
+                )))))
+            }
+            // `Std.env("HOME")` is the value of an environment variable, or None if it isn't set
+            "env" => {
+                let granted = self.permissions.env;
+                Some(Rc::new(RefCell::new(Value::Function(Function::Builtin(
+                    BuiltinFunction::new(move |args| {
+                        VmPermissions::require(granted, "Std.env", "env")?;
+                        let name = match args.first().map(|arg| arg.borrow().clone()) {
+                            Some(Value::String(name)) => name,
+                            _ => raise(ErrorKind::Type, "Std.env expects a string")?,
+                        };
+                        Ok(std::env::var(&*name)
+                            .map_or(Value::None, |value| Value::String(value.into())))
+                    }),
+                )))))
+            }
+            "Time" => Some(Rc::new(RefCell::new(Value::Class(Rc::new(TimeClass {
+                granted: self.permissions.time,
+            }))))),
+            "Gc" => Some(Rc::new(RefCell::new(Value::Class(Rc::new(GcClass))))),
             _ => None,
         }
     }
-
-    fn call_magic(&self, method: MagicMethod, args: Vec<Rc<RefCell<Value>>>) -> Rc<RefCell<Value>> {
-        unimplemented!()
-    }
 }
 
+/// The clock, which needs the `time` permission
 #[derive(Debug)]
-pub struct TimeClass;
-
-impl Class for TimeClass {
-    fn create_instance(&self) -> Rc<dyn ClassInstance> {
-        Rc::new(TimeInstance)
-    }
+pub struct TimeClass {
+    granted: bool,
 }
 
-#[derive(Debug)]
-pub struct TimeInstance;
-
-impl ClassInstance for TimeInstance {
-    fn as_any(&self) -> &dyn std::any::Any {
-        self
+impl Class for TimeClass {
+    fn name(&self) -> &str {
+        "Time"
     }
 
-    fn get_field(&self, name: &str) -> Option<Rc<RefCell<Value>>> {
+    fn get_static(&self, name: &str) -> Option<Rc<RefCell<Value>>> {
+        let granted = self.granted;
         match name {
             "now" => Some(Rc::new(RefCell::new(Value::Function(Function::Builtin(
-                BuiltinFunction::new(|_| {
-                    Value::ClassInstance(Rc::new(DatetimeInstance { value: Utc::now() }))
+                BuiltinFunction::new(move |_| {
+                    VmPermissions::require(granted, "Std.Time.now", "time")?;
+                    Ok(Value::ClassInstance(Rc::new(DatetimeInstance {
+                        value: Utc::now(),
+                    })))
                 }),
             ))))),
             "sleep" => Some(Rc::new(RefCell::new(Value::Function(Function::Builtin(
-                BuiltinFunction::new(|args| {
-                    let duration = args[0]
-                        .borrow()
-                        .as_any()
-                        .downcast_ref::<NumberInstance>()
-                        .expect("Expected number")
-                        .value;
-                    std::thread::sleep(std::time::Duration::from_secs_f64(duration));
-                    Value::None
+                BuiltinFunction::new(move |args| {
+                    VmPermissions::require(granted, "Std.Time.sleep", "time")?;
+                    let Some(duration) = args.first().and_then(|arg| arg.borrow().as_number())
+                    else {
+                        return raise(ErrorKind::Type, "Time.sleep expects a number");
+                    };
+                    let Ok(duration) = std::time::Duration::try_from_secs_f64(duration) else {
+                        return raise(
+                            ErrorKind::Value,
+                            format!("Time.sleep expects a duration in seconds, got {}", duration),
+                        );
+                    };
+                    std::thread::sleep(duration);
+                    Ok(Value::None)
                 }),
             ))))),
             _ => None,
         }
     }
+}
+
+/// The cycle collector, which runs on its own but can be started with `Std.Gc.collect()`
+#[derive(Debug)]
+pub struct GcClass;
+
+impl Class for GcClass {
+    fn name(&self) -> &str {
+        "Gc"
+    }
 
-    fn call_magic(&self, method: MagicMethod, args: Vec<Rc<RefCell<Value>>>) -> Rc<RefCell<Value>> {
-        unimplemented!()
+    fn get_static(&self, name: &str) -> Option<Rc<RefCell<Value>>> {
+        let function = match name {
+            // Returns the number of objects freed
+            "collect" => BuiltinFunction::new(|_| Ok(Value::Number(gc::collect() as f64))),
+            // Returns a map with the numbers of tracked objects, collections and freed objects
+            "stats" => BuiltinFunction::new(|_| {
+                let stats = gc::stats();
+                let entries = [
+                    ("tracked", stats.tracked),
+                    ("collections", stats.collections),
+                    ("freed", stats.freed),
+                ]
+                .into_iter()
+                .map(|(key, value)| {
+                    (
+                        Rc::new(RefCell::new(Value::String(key.into()))),
+                        Rc::new(RefCell::new(Value::Number(value as f64))),
+                    )
+                })
+                .collect();
+                Ok(Value::ClassInstance(Rc::new(MapInstance::new(entries))))
+            }),
+            _ => return None,
+        };
+        Some(Rc::new(RefCell::new(Value::Function(Function::Builtin(
+            function,
+        )))))
     }
 }
 
 // Datetime class
-// Represents a date and time value
+// Represents a date and time value, created with `Datetime(2024, 1, 1)` or `Std.Time.now()`
 #[derive(Debug)]
 pub struct DatetimeClass;
 
 impl Class for DatetimeClass {
-    fn create_instance(&self) -> Rc<dyn ClassInstance> {
-        Rc::new(DatetimeInstance { value: Utc::now() })
+    fn name(&self) -> &str {
+        "Datetime"
+    }
+
+    /// `Datetime(year, month, day)`, optionally followed by the hour, minute and second, in UTC
+    fn construct(&self, args: Vec<Rc<RefCell<Value>>>) -> Result<Value, RuntimeError> {
+        if !(3..=6).contains(&args.len()) {
+            return raise(
+                ErrorKind::Arity,
+                format!("Datetime expects 3 to 6 arguments, got {}", args.len()),
+            );
+        }
+        let mut parts = [0; 6];
+        for (part, arg) in parts.iter_mut().zip(&args) {
+            let number = arg
+                .borrow()
+                .as_number()
+                .filter(|value| value.fract() == 0.0 && *value >= 0.0);
+            let Some(number) = number else {
+                return raise(
+                    ErrorKind::Type,
+                    format!("Datetime expects integers, got {}", arg.borrow()),
+                );
+            };
+            *part = number as u32;
+        }
+        let [year, month, day, hour, minute, second] = parts;
+        let value = Utc
+            .with_ymd_and_hms(year as i32, month, day, hour, minute, second)
+            .single();
+        let Some(value) = value else {
+            return raise(
+                ErrorKind::Value,
+                format!(
+                    "Invalid date {}-{:02}-{:02} {:02}:{:02}:{:02}",
+                    year, month, day, hour, minute, second
+                ),
+            );
+        };
+        Ok(Value::ClassInstance(Rc::new(DatetimeInstance { value })))
     }
 }
 
@@ -118,6 +217,10 @@ impl std::fmt::Debug for DatetimeInstance {
 }
 
 impl ClassInstance for DatetimeInstance {
+    fn class_name(&self) -> &str {
+        "Datetime"
+    }
+
     fn as_any(&self) -> &dyn std::any::Any {
         self
     }
@@ -128,16 +231,21 @@ impl ClassInstance for DatetimeInstance {
                 let datetime = self.value;
                 Some(Rc::new(RefCell::new(Value::Function(Function::Builtin(
                     BuiltinFunction::new(move |args| {
-                        let format = args[0]
-                            .borrow()
-                            .as_any()
-                            .downcast_ref::<StringInstance>()
-                            .expect("Expected string")
-                            .value
-                            .clone();
-                        let datetime = datetime;
-                        let formatted = datetime.format(&format).to_string();
-                        Value::ClassInstance(Rc::new(StringInstance { value: formatted }))
+                        let format = args
+                            .first()
+                            .and_then(|arg| arg.borrow().as_str().map(str::to_string));
+                        let Some(format) = format else {
+                            return raise(ErrorKind::Type, "format expects a string");
+                        };
+                        // An invalid format fails the formatting instead of panicking
+                        let mut formatted = String::new();
+                        if write!(formatted, "{}", datetime.format(&format)).is_err() {
+                            return raise(
+                                ErrorKind::Value,
+                                format!("Invalid date format '{}'", format),
+                            );
+                        }
+                        Ok(Value::String(formatted.into()))
                     }),
                 )))))
             }
@@ -145,7 +253,11 @@ impl ClassInstance for DatetimeInstance {
         }
     }
 
-    fn call_magic(&self, method: MagicMethod, args: Vec<Rc<RefCell<Value>>>) -> Rc<RefCell<Value>> {
-        unimplemented!()
+    fn call_magic(
+        &self,
+        method: MagicMethod,
+        args: Vec<Rc<RefCell<Value>>>,
+    ) -> Result<Rc<RefCell<Value>>, RuntimeError> {
+        unsupported(method, self.class_name(), &args)
     }
 }
@@ -1,76 +1,49 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{cell::RefCell, collections::HashSet, rc::Rc};
 
 use crate::{
-    class::{Class, ClassInstance},
-    MagicMethod, Value,
+    class::Class,
+    errors::{raise, ErrorKind, RuntimeError},
+    Value,
 };
 
 #[derive(Debug)]
 pub struct StringClass;
 
 impl Class for StringClass {
-    fn create_instance(&self) -> Rc<dyn ClassInstance> {
-        Rc::new(StringInstance {
-            value: String::new(),
-        })
+    fn name(&self) -> &str {
+        "String"
     }
-}
-
-pub struct StringInstance {
-    pub value: String,
-}
 
-impl std::fmt::Debug for StringInstance {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:}", self.value)
+    /// `String(x)` converts any value to its string form, `String()` is empty
+    fn construct(&self, args: Vec<Rc<RefCell<Value>>>) -> Result<Value, RuntimeError> {
+        let value = match args.as_slice() {
+            [] => String::new(),
+            [value] => value.borrow().to_string(),
+            _ => raise(
+                ErrorKind::Arity,
+                format!("String expects at most 1 argument, got {}", args.len()),
+            )?,
+        };
+        Ok(Value::String(value.into()))
     }
 }
 
-impl ClassInstance for StringInstance {
-    fn as_any(&self) -> &dyn std::any::Any {
-        self
-    }
-
-    fn get_field(&self, name: &str) -> Option<Rc<RefCell<Value>>> {
-        if name == "value" {
-            Some(Rc::new(RefCell::new(Value::ClassInstance(Rc::new(
-                StringInstance {
-                    value: self.value.clone(),
-                },
-            )))))
-        } else {
-            None
-        }
-    }
-
-    fn call_magic(&self, method: MagicMethod, args: Vec<Rc<RefCell<Value>>>) -> Rc<RefCell<Value>> {
-        let rhs = if let Value::ClassInstance(instance) = &*args[1].borrow() {
-            if let Some(Value::ClassInstance(rhs_instance)) =
-                instance.get_field("value").map(|v| v.borrow().clone())
-            {
-                if let Some(rhs_instance) = rhs_instance.as_any().downcast_ref::<StringInstance>() {
-                    rhs_instance.value.clone()
-                } else {
-                    panic!("Invalid type for rhs")
-                }
-            } else {
-                panic!("Invalid type for rhs")
-            }
-        } else {
-            panic!("Invalid type for rhs")
-        };
+thread_local! {
+    static INTERNED: RefCell<HashSet<Rc<str>>> = RefCell::new(HashSet::new());
+}
 
-        let result = match method {
-            MagicMethod::Add => format!("{}{}", self.value, rhs),
-            MagicMethod::Equal => return Rc::new(RefCell::new(Value::Boolean(self.value == rhs))),
-            MagicMethod::NotEqual => {
-                return Rc::new(RefCell::new(Value::Boolean(self.value != rhs)))
+/// The shared copy of a string. The string literals of the scripts are interned,
+/// so that a literal evaluated many times, like in a loop, is never copied.
+pub fn intern(text: &str) -> Rc<str> {
+    INTERNED.with(|interned| {
+        let mut interned = interned.borrow_mut();
+        match interned.get(text) {
+            Some(string) => Rc::clone(string),
+            None => {
+                let string: Rc<str> = Rc::from(text);
+                interned.insert(Rc::clone(&string));
+                string
             }
-            _ => unimplemented!(),
-        };
-
-        Rc::new(RefCell::new(Value::ClassInstance(Rc::new(
-            StringInstance { value: result },
-        ))))
-    }
+        }
+    })
 }
@@ -0,0 +1,113 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    class::{Class, ClassInstance, Items},
+    errors::{raise, unsupported, ErrorKind, RuntimeError},
+    gc::Tracer,
+    values_equal, BuiltinFunction, Function, MagicMethod, Value,
+};
+
+#[derive(Debug)]
+pub struct TupleClass;
+
+impl Class for TupleClass {
+    fn name(&self) -> &str {
+        "Tuple"
+    }
+}
+
+/// A fixed-size group of values, like `(1, "a")`.
+/// It's what functions return when they return several values (`return a, b`).
+pub struct TupleInstance {
+    pub items: Vec<Rc<RefCell<Value>>>,
+}
+
+impl std::fmt::Debug for TupleInstance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let items: Vec<String> = self
+            .items
+            .iter()
+            .map(|item| item.borrow().to_string())
+            .collect();
+        if items.len() == 1 {
+            write!(f, "({},)", items[0])
+        } else {
+            write!(f, "({})", items.join(", "))
+        }
+    }
+}
+
+impl ClassInstance for TupleInstance {
+    fn class_name(&self) -> &str {
+        "Tuple"
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    fn trace(&self, tracer: &mut Tracer) {
+        for item in &self.items {
+            tracer.visit(item);
+        }
+    }
+
+    /// `Tuple(a, b)` matches the tuples of two items
+    fn destructure(&self) -> Option<Vec<Rc<RefCell<Value>>>> {
+        Some(self.items.clone())
+    }
+
+    fn iterate(&self) -> Option<Items> {
+        Some(Box::new(self.items.clone().into_iter().map(Ok)))
+    }
+
+    fn get_field(&self, name: &str) -> Option<Rc<RefCell<Value>>> {
+        let items = self.items.clone();
+        let function = match name {
+            "len" => BuiltinFunction::new(move |_args| Ok(Value::Number(items.len() as f64))),
+            "get" => BuiltinFunction::new(move |args| {
+                let Some(index) = args.first().and_then(|arg| arg.borrow().as_number()) else {
+                    return raise(ErrorKind::Type, "get expects a number");
+                };
+                let index = index as usize;
+                let Some(item) = items.get(index) else {
+                    return raise(ErrorKind::Value, format!("Index {} out of range", index));
+                };
+                let value = item.borrow().clone();
+                Ok(value)
+            }),
+            _ => return None,
+        };
+        Some(Rc::new(RefCell::new(Value::Function(Function::Builtin(
+            function,
+        )))))
+    }
+
+    fn call_magic(
+        &self,
+        method: MagicMethod,
+        args: Vec<Rc<RefCell<Value>>>,
+    ) -> Result<Rc<RefCell<Value>>, RuntimeError> {
+        let rhs = args.get(1).map(|rhs| rhs.borrow());
+        let Some(rhs) = rhs
+            .as_ref()
+            .and_then(|rhs| rhs.as_any().downcast_ref::<TupleInstance>())
+        else {
+            return unsupported(method, self.class_name(), &args);
+        };
+
+        let result = match method {
+            MagicMethod::Equal | MagicMethod::NotEqual => {
+                let equal = self.items.len() == rhs.items.len()
+                    && self
+                        .items
+                        .iter()
+                        .zip(rhs.items.iter())
+                        .all(|(lhs, rhs)| values_equal(lhs, rhs));
+                Value::Boolean(equal == (method == MagicMethod::Equal))
+            }
+            _ => unsupported(method, self.class_name(), &args)?,
+        };
+        Ok(Rc::new(RefCell::new(result)))
+    }
+}
@@ -0,0 +1,326 @@
+//! The macros exposing Rust structs to the scripts as classes: `#[derive(FruttaClass)]` for their fields,
+//! and `#[frutta_methods]` for the methods of one of their `impl` blocks.
+//! They are re-exported by the vm crate, and the code they generate refers to it as `::vm`.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, Attribute, Data, DeriveInput, Error, FnArg, ImplItem, ItemImpl, LitStr,
+    ReturnType, Type,
+};
+
+/// The most arguments a native function can take
+const MAX_ARGUMENTS: usize = 6;
+
+/// Expose a struct with named fields as a class, registered with `VM::register_class`.
+///
+/// The class is called with the fields in order, like `Point(1, 2)`, and the scripts can read and assign them.
+/// The fields are converted with `FromValue` and `IntoValue`, and the struct must implement `Clone`.
+///
+/// - `#[frutta(name = "...")]` on the struct names the class, the name of the struct by default
+/// - `#[frutta(methods)]` on the struct tells that a `#[frutta_methods]` block gives its methods
+/// - `#[frutta(readonly)]` on a field prevents the scripts from assigning it
+/// - `#[frutta(skip)]` on a field hides it from the scripts, its default value being used by the constructor
+#[proc_macro_derive(FruttaClass, attributes(frutta))]
+pub fn derive_frutta_class(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    class(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Expose the methods of an `impl` block to the scripts, for a struct deriving `FruttaClass` with
+/// `#[frutta(methods)]`. Methods taking `self` are called on the instances, like `p.norm()`, and the others on the
+/// class, like `Point.origin()`. They take up to 6 arguments converted with `FromValue`, and return a value
+/// implementing `IntoValue`, or a `Result` whose error converts into a `RuntimeError`.
+///
+/// - `#[frutta(name = "...")]` on a method names it in the scripts
+/// - `#[frutta(skip)]` on a method hides it from the scripts
+#[proc_macro_attribute]
+pub fn frutta_methods(args: TokenStream, input: TokenStream) -> TokenStream {
+    if !args.is_empty() {
+        let args = TokenStream2::from(args);
+        return Error::new_spanned(args, "#[frutta_methods] takes no arguments")
+            .into_compile_error()
+            .into();
+    }
+    let input = parse_macro_input!(input as ItemImpl);
+    methods(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// The options given by `#[frutta(...)]` attributes
+#[derive(Default)]
+struct Options {
+    name: Option<String>,
+    methods: bool,
+    readonly: bool,
+    skip: bool,
+}
+
+/// Read the `#[frutta(...)]` attributes of an item, which can only have the `allowed` options
+fn read_options(attrs: &[Attribute], allowed: &[&str]) -> syn::Result<Options> {
+    let mut options = Options::default();
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("frutta")) {
+        attr.parse_nested_meta(|meta| {
+            let option = meta
+                .path
+                .get_ident()
+                .map(ToString::to_string)
+                .unwrap_or_default();
+            if !allowed.contains(&option.as_str()) {
+                return Err(meta.error(format!(
+                    "unknown option, expected one of: {}",
+                    allowed.join(", ")
+                )));
+            }
+            match option.as_str() {
+                "name" => options.name = Some(meta.value()?.parse::<LitStr>()?.value()),
+                "methods" => options.methods = true,
+                "readonly" => options.readonly = true,
+                _ => options.skip = true,
+            }
+            Ok(())
+        })?;
+    }
+    Ok(options)
+}
+
+fn class(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let ty = &input.ident;
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &input.generics,
+            "FruttaClass can't be derived for generic structs",
+        ));
+    }
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            syn::Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(Error::new_spanned(
+                    ty,
+                    "FruttaClass can only be derived for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(Error::new_spanned(
+                ty,
+                "FruttaClass can only be derived for structs",
+            ))
+        }
+    };
+    let options = read_options(&input.attrs, &["name", "methods"])?;
+    let name = options.name.unwrap_or_else(|| ty.to_string());
+
+    let mut initializers = Vec::new();
+    let mut exposed = Vec::new();
+    let mut assignable = Vec::new();
+    for field in fields {
+        let ident = field.ident.as_ref().expect("named fields have a name");
+        let field_options = read_options(&field.attrs, &["readonly", "skip"])?;
+        if field_options.skip {
+            initializers.push(quote!(#ident: ::core::default::Default::default()));
+            continue;
+        }
+        let index = exposed.len();
+        initializers.push(quote!(#ident: ::vm::native::argument(args, #index)?));
+        exposed.push(ident);
+        if !field_options.readonly {
+            assignable.push(ident);
+        }
+    }
+    let count = exposed.len();
+    let exposed_names = exposed.iter().map(|ident| ident.to_string());
+    let assignable_names = assignable.iter().map(|ident| ident.to_string());
+    let methods = if options.methods {
+        quote!()
+    } else {
+        quote!(impl ::vm::native::FruttaMethods for #ty {})
+    };
+
+    Ok(quote! {
+        impl ::vm::native::NativeClass for #ty {
+            const NAME: &'static str = #name;
+
+            fn construct(args: &[::vm::Value]) -> ::core::result::Result<Self, ::vm::errors::RuntimeError> {
+                ::vm::native::check_arity(#name, args, #count)?;
+                ::core::result::Result::Ok(#ty { #(#initializers),* })
+            }
+
+            fn get_static(name: &str) -> ::core::option::Option<::vm::Value> {
+                <Self as ::vm::native::FruttaMethods>::function(name)
+            }
+
+            fn into_instance(self) -> ::std::rc::Rc<dyn ::vm::class::ClassInstance> {
+                ::vm::native::Instance::wrap(self)
+            }
+
+            fn from_instance(instance: &dyn ::vm::class::ClassInstance) -> ::core::option::Option<Self> {
+                ::vm::native::Instance::<Self>::get(instance)
+            }
+        }
+
+        impl ::vm::native::FruttaClass for #ty {
+            #[allow(unreachable_code)]
+            fn get(&self, name: &str) -> ::core::option::Option<::vm::Value> {
+                let value = match name {
+                    #(#exposed_names => ::vm::native::IntoValue::into_value(
+                        ::core::clone::Clone::clone(&self.#exposed)
+                    ),)*
+                    _ => return ::core::option::Option::None,
+                };
+                ::core::option::Option::Some(value)
+            }
+
+            #[allow(unreachable_code)]
+            fn set(
+                &mut self,
+                name: &str,
+                value: &::vm::Value,
+            ) -> ::core::option::Option<::core::result::Result<(), ::vm::errors::RuntimeError>> {
+                let result = match name {
+                    #(#assignable_names => ::vm::native::FromValue::from_value(value)
+                        .map(|value| self.#assignable = value),)*
+                    _ => return ::core::option::Option::None,
+                };
+                ::core::option::Option::Some(result)
+            }
+
+            fn fields(&self) -> ::std::vec::Vec<::vm::Value> {
+                ::std::vec![#(::vm::native::IntoValue::into_value(
+                    ::core::clone::Clone::clone(&self.#exposed)
+                )),*]
+            }
+        }
+
+        #methods
+    })
+}
+
+fn methods(mut input: ItemImpl) -> syn::Result<TokenStream2> {
+    if input.trait_.is_some() || !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &input.self_ty,
+            "#[frutta_methods] can only be used on the impl block of a struct without generics",
+        ));
+    }
+    let mut methods = Vec::new();
+    let mut functions = Vec::new();
+    for item in &mut input.items {
+        let ImplItem::Fn(method) = item else {
+            continue;
+        };
+        let options = read_options(&method.attrs, &["name", "skip"])?;
+        method.attrs.retain(|attr| !attr.path().is_ident("frutta"));
+        if options.skip {
+            continue;
+        }
+        let sig = &method.sig;
+        if !sig.generics.params.is_empty() || sig.asyncness.is_some() {
+            return Err(Error::new_spanned(
+                sig,
+                "generic and async methods can't be exposed, skip them with #[frutta(skip)]",
+            ));
+        }
+        let ident = &sig.ident;
+        let name = options.name.unwrap_or_else(|| ident.to_string());
+
+        let mut receiver = None;
+        let mut params = Vec::new();
+        let mut args = Vec::new();
+        for (index, input) in sig.inputs.iter().enumerate() {
+            match input {
+                FnArg::Receiver(this) if this.colon_token.is_some() => {
+                    return Err(Error::new_spanned(
+                        this,
+                        "only self, &self and &mut self receivers can be exposed",
+                    ))
+                }
+                FnArg::Receiver(this) => {
+                    receiver = Some(match (&this.reference, &this.mutability) {
+                        (None, _) => quote!(::core::clone::Clone::clone(&*this.borrow())),
+                        (Some(_), None) => quote!(&*this.borrow()),
+                        (Some(_), Some(_)) => quote!(&mut *this.borrow_mut()),
+                    });
+                }
+                FnArg::Typed(arg) => {
+                    let arg_name = format_ident!("arg{}", index);
+                    let ty = &arg.ty;
+                    params.push(quote!(#arg_name: #ty));
+                    args.push(arg_name);
+                }
+            }
+        }
+        if params.len() > MAX_ARGUMENTS {
+            return Err(Error::new_spanned(
+                &sig.inputs,
+                format!("exposed methods take at most {} arguments", MAX_ARGUMENTS),
+            ));
+        }
+
+        let call = match &receiver {
+            Some(receiver) => quote!(Self::#ident(#receiver, #(#args),*)),
+            None => quote!(Self::#ident(#(#args),*)),
+        };
+        let body = if returns_result(&sig.output) {
+            call
+        } else {
+            quote!(::core::result::Result::Ok::<_, ::vm::errors::RuntimeError>(#call))
+        };
+        if receiver.is_some() {
+            methods.push(quote! {
+                #name => {
+                    let this = ::std::rc::Rc::clone(instance);
+                    ::vm::native::function(#name, move |#(#params),*| #body)
+                }
+            });
+        } else {
+            functions.push(quote! {
+                #name => ::vm::native::function(#name, |#(#params),*| #body),
+            });
+        }
+    }
+
+    let ty = &input.self_ty;
+    Ok(quote! {
+        #input
+
+        impl ::vm::native::FruttaMethods for #ty {
+            #[allow(unused_variables, unreachable_code)]
+            fn method(
+                instance: &::std::rc::Rc<::core::cell::RefCell<Self>>,
+                name: &str,
+            ) -> ::core::option::Option<::vm::Value> {
+                let method = match name {
+                    #(#methods)*
+                    _ => return ::core::option::Option::None,
+                };
+                ::core::option::Option::Some(method)
+            }
+
+            #[allow(unreachable_code)]
+            fn function(name: &str) -> ::core::option::Option<::vm::Value> {
+                let function = match name {
+                    #(#functions)*
+                    _ => return ::core::option::Option::None,
+                };
+                ::core::option::Option::Some(function)
+            }
+        }
+    })
+}
+
+/// Whether a method returns a `Result`, whose error is reported to the scripts
+fn returns_result(output: &ReturnType) -> bool {
+    match output {
+        ReturnType::Type(_, ty) => matches!(
+            &**ty,
+            Type::Path(path) if path.path.segments.last().is_some_and(|segment| segment.ident == "Result")
+        ),
+        ReturnType::Default => false,
+    }
+}